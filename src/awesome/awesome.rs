@@ -1,7 +1,7 @@
 //! TODO Fill in
 
 use super::xproperty::{XProperty, XPropertyType, PROPERTIES};
-use super::{signal, XCB_CONNECTION_HANDLE};
+use super::{signal, xcb_connection};
 use awesome::lua::NEXT_LUA;
 use cairo::{self, ImageSurface, ImageSurfaceData};
 use gdk_pixbuf::{Pixbuf, PixbufExt};
@@ -129,9 +129,14 @@ fn register_xproperty<'lua>(lua: &'lua Lua,
     let name = CString::new(name_rust.clone()).expect("XProperty was not CString");
     let arg_type = XPropertyType::from_string(v_type.clone())
         .ok_or(rlua::Error::RuntimeError(format!("{} not a valid xproperty", v_type)))?;
+    let raw_con = match xcb_connection(lua)? {
+        Some(con) => con,
+        None => {
+            debug!("Not registering xproperty {}, XWayland is not running", name_rust);
+            return Ok(())
+        }
+    };
     unsafe {
-        let raw_con = lua.named_registry_value::<LightUserData>(XCB_CONNECTION_HANDLE)?
-                         .0 as _;
         let atom_c = xproto::xcb_intern_atom_unchecked(raw_con,
                                                        false as u8,
                                                        name.to_bytes().len() as u16,
@@ -160,8 +165,10 @@ fn register_xproperty<'lua>(lua: &'lua Lua,
 
 /// Get layout short names
 fn xkb_get_group_names<'lua>(lua: &'lua Lua, _: ()) -> rlua::Result<Value<'lua>> {
-    let xcb_con = lua.named_registry_value::<LightUserData>(XCB_CONNECTION_HANDLE)?
-                     .0;
+    let xcb_con = match xcb_connection(lua)? {
+        Some(con) => con,
+        None => return Ok(Value::Nil)
+    };
     unsafe {
         let con = Connection::from_raw_conn(xcb_con as _);
         let raw_con = con.get_raw_conn();
@@ -316,9 +323,11 @@ fn xkb_set_layout_group(_: &Lua, _group: i32) -> rlua::Result<()> {
 
 fn xkb_get_layout_group<'lua>(lua: &'lua Lua, _: ()) -> rlua::Result<Value<'lua>> {
     use xcb::ffi::xkb;
+    let raw_con = match xcb_connection(lua)? {
+        Some(con) => con,
+        None => return Ok(Value::Nil)
+    };
     unsafe {
-        let raw_con = lua.named_registry_value::<LightUserData>(XCB_CONNECTION_HANDLE)?
-                         .0 as _;
         let state_c = xkb::xcb_xkb_get_state_unchecked(raw_con, xkb::XCB_XKB_ID_USE_CORE_KBD as _);
         let state_r = xkb::xcb_xkb_get_state_reply(raw_con, state_c, ptr::null_mut());
         if state_r.is_null() {
@@ -1,13 +1,44 @@
 use super::class::{self, Class};
+use super::client;
+use super::drawin::{self, Drawin};
+use super::lua::{mods_to_lua, num_to_mods};
 use super::object::{self, Object, Objectable};
 use super::property::Property;
+use super::root::ROOT_BUTTONS_HANDLE;
 use super::signal;
 use rlua::{self, AnyUserData, Lua, Table, ToLua, UserData, UserDataMethods, Value};
 use std::default::Default;
 use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
 use wlroots::events::key_events::Key;
+use wlroots::xkbcommon::xkb::keysyms::{KEY_Caps_Lock, KEY_Meta_L};
+use wlroots::KeyboardModifier;
 use xcb::ffi::xproto::xcb_button_t;
 
+use compositor::View;
+
+/// Where the button bindings of an object are kept, in its wrapper table.
+const BUTTONS_KEY: &str = "__buttons";
+
+/// Modifiers that don't change which bindings match, i.e the lock keys.
+const IGNORED_MODIFIERS: [Key; 2] = [KEY_Caps_Lock, KEY_Meta_L];
+
+/// A button that was pressed or released for the bindings of Lua, e.g to
+/// scroll through the tags on the root window.
+#[derive(Debug, Clone)]
+pub struct ButtonPress {
+    /// The number Awesome knows the button by, e.g 4 for scrolling up.
+    pub button: u32,
+    pub pressed: bool,
+    /// Where the pointer is, in output layout coordinates.
+    pub x: f64,
+    pub y: f64,
+    pub modifiers: KeyboardModifier,
+    /// The view under the pointer, whose client bindings get the button.
+    /// The view gets the button as well.
+    pub view: Option<Rc<View>>
+}
+
 #[derive(Clone, Debug)]
 pub struct ButtonState {
     button: xcb_button_t,
@@ -65,6 +96,26 @@ impl<'lua> Button<'lua> {
         button.modifiers = mods_to_rust(mods)?;
         Ok(())
     }
+
+    /// Whether the binding is for the button, with exactly these modifiers
+    /// held down.
+    ///
+    /// A binding for button 0 is for any button, like in Awesome.
+    pub fn matches(&self, button: xcb_button_t, modifiers: &[Key]) -> rlua::Result<bool> {
+        let state = self.state()?;
+        let same_button = state.button == 0 || state.button == button;
+        Ok(same_button && relevant_modifiers(&state.modifiers) == relevant_modifiers(modifiers))
+    }
+}
+
+fn relevant_modifiers(modifiers: &[Key]) -> Vec<Key> {
+    let mut relevant = modifiers.iter()
+                                .cloned()
+                                .filter(|modifier| !IGNORED_MODIFIERS.contains(modifier))
+                                .collect::<Vec<_>>();
+    relevant.sort();
+    relevant.dedup();
+    relevant
 }
 
 impl<'lua> ToLua<'lua> for Button<'lua> {
@@ -120,6 +171,110 @@ fn set_modifiers<'lua>(lua: &'lua Lua,
 }
 
 fn get_modifiers<'lua>(lua: &'lua Lua, obj: AnyUserData<'lua>) -> rlua::Result<Value<'lua>> {
-    use lua::mods_to_lua;
     mods_to_lua(lua, &Button::cast(obj.into())?.modifiers()?).map(Value::Table)
 }
+
+/// Get or set the button bindings of an object, e.g `drawin:buttons{...}`.
+pub fn object_buttons<'lua>(lua: &'lua Lua,
+                            (object, buttons): (AnyUserData<'lua>, Value<'lua>))
+                            -> rlua::Result<Value<'lua>> {
+    let table = Object::from(object).table()?;
+    match buttons {
+        Value::Table(buttons) => {
+            // NOTE We make a copy so they can't modify references.
+            table.set(BUTTONS_KEY, copy_bindings(lua, buttons.clone())?)?;
+            Ok(Value::Table(buttons))
+        }
+        Value::Nil => {
+            match table.get::<_, Option<Table>>(BUTTONS_KEY)? {
+                Some(buttons) => copy_bindings(lua, buttons).map(Value::Table),
+                None => lua.create_table().map(Value::Table)
+            }
+        }
+        v => {
+            Err(rlua::Error::RuntimeError(format!("Expected nil or array \
+                                                   of buttons, got {:?}",
+                                                  v)))
+        }
+    }
+}
+
+fn copy_bindings<'lua>(lua: &'lua Lua, bindings: Table<'lua>) -> rlua::Result<Table<'lua>> {
+    let copy = lua.create_table()?;
+    for entry in bindings.pairs() {
+        let (key, value) = entry?;
+        copy.set::<Value, Value>(key, value)?;
+    }
+    Ok(copy)
+}
+
+/// Emits "press" or "release" on the bindings for the button, of the client
+/// or the drawin under the pointer or else of the root window.
+///
+/// The drawin also gets "button::press" or "button::release", with where
+/// it was clicked.
+pub fn emit_button_bindings(lua: &Lua, press: &ButtonPress) -> rlua::Result<()> {
+    if let Some(ref view) = press.view {
+        let bindings = match client::client_of(lua, view)? {
+            Some(client) => {
+                Object::from(client).table()?
+                                    .get::<_, Option<Vec<AnyUserData>>>(BUTTONS_KEY)?
+            }
+            None => None
+        };
+        return emit_bindings(lua, press, bindings.unwrap_or_default())
+    }
+    let bindings = match drawin::drawin_at(lua, press.x, press.y)? {
+        Some(drawin) => {
+            let geometry = Drawin::cast(drawin.clone().into())?.get_geometry()?;
+            let (x, y) = (press.x as i32 - geometry.origin.x, press.y as i32 - geometry.origin.y);
+            let name = if press.pressed { "button::press" } else { "button::release" };
+            let modifiers = mods_to_lua(lua, &num_to_mods(press.modifiers))?;
+            signal::emit_object_signal(lua,
+                                       drawin.clone().into(),
+                                       name.into(),
+                                       (x, y, press.button, modifiers))?;
+            Object::from(drawin).table()?
+                                .get::<_, Option<Vec<AnyUserData>>>(BUTTONS_KEY)?
+        }
+        None => lua.named_registry_value::<Option<Vec<AnyUserData>>>(ROOT_BUTTONS_HANDLE)?
+    };
+    emit_bindings(lua, press, bindings.unwrap_or_default())
+}
+
+fn emit_bindings(lua: &Lua, press: &ButtonPress, bindings: Vec<AnyUserData>) -> rlua::Result<()> {
+    let name = if press.pressed { "press" } else { "release" };
+    let modifiers = num_to_mods(press.modifiers);
+    for binding in bindings {
+        let obj: Object = binding.into();
+        let button = match Button::cast(obj.clone()) {
+            Ok(button) => button,
+            Err(_) => continue
+        };
+        if button.matches(press.button as xcb_button_t, &modifiers)? {
+            if let Err(err) = signal::emit_object_signal(lua, obj, name.into(), ()) {
+                warn!("Could not emit the signal for button {}: {:?}", press.button, err);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::relevant_modifiers;
+    use wlroots::xkbcommon::xkb::keysyms::{KEY_Caps_Lock, KEY_Control_L, KEY_Meta_L,
+                                           KEY_Shift_L, KEY_Super_L};
+
+    #[test]
+    fn lock_keys_are_ignored() {
+        assert_eq!(relevant_modifiers(&[KEY_Caps_Lock, KEY_Meta_L]), vec![]);
+        assert_eq!(relevant_modifiers(&[KEY_Super_L, KEY_Caps_Lock]), vec![KEY_Super_L]);
+    }
+
+    #[test]
+    fn order_does_not_matter() {
+        assert_eq!(relevant_modifiers(&[KEY_Control_L, KEY_Shift_L, KEY_Control_L]),
+                   relevant_modifiers(&[KEY_Shift_L, KEY_Control_L]));
+    }
+}
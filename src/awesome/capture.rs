@@ -0,0 +1,69 @@
+//! Captures of what is shown on the outputs (`root.content` and
+//! `screen:content`), and of the clients (`client:content`).
+//!
+//! The outputs are read back when they draw their next frame, so these take
+//! a callback which is called with the capture as a cairo surface, or with
+//! `nil` if it couldn't be made:
+//!
+//! ```lua
+//! root.content(function(surface) ... end)
+//! ```
+
+use cairo::ImageSurface;
+use rlua::{self, Function, Lua, Table, Value};
+use std::rc::Rc;
+use wlroots::Area;
+
+use compositor::{self, View};
+
+use super::drawable;
+
+const CAPTURE_CALLBACKS: &str = "__capture_callbacks";
+
+/// Init the methods defined on this interface.
+pub fn init(lua: &Lua) -> rlua::Result<()> {
+    lua.set_named_registry_value(CAPTURE_CALLBACKS, lua.create_table()?)
+}
+
+/// Captures an area of the output layout at the given scale, and calls
+/// `callback` with it once it has been drawn.
+pub fn capture_area(lua: &Lua, area: Area, scale: f32, callback: Function) -> rlua::Result<()> {
+    wait_for_capture(lua, compositor::capture_area(area, scale), callback)
+}
+
+/// Captures everything in the output layout, and calls `callback` with it
+/// once it has been drawn.
+pub fn capture_root(lua: &Lua, callback: Function) -> rlua::Result<()> {
+    wait_for_capture(lua, compositor::capture_root(), callback)
+}
+
+/// Captures the contents of a view on their own, and calls `callback` with
+/// them once they have been drawn.
+pub fn capture_view(lua: &Lua, view: Rc<View>, callback: Function) -> rlua::Result<()> {
+    wait_for_capture(lua, compositor::capture_view(view), callback)
+}
+
+fn wait_for_capture(lua: &Lua, id: Result<u64, String>, callback: Function) -> rlua::Result<()> {
+    match id {
+        Ok(id) => {
+            let callbacks = lua.named_registry_value::<Table>(CAPTURE_CALLBACKS)?;
+            callbacks.set(id, callback)
+        }
+        Err(err) => {
+            warn!("Could not capture: {}", err);
+            callback.call(Value::Nil)
+        }
+    }
+}
+
+/// Hands a capture to the callback that is waiting for it, if Lua wasn't
+/// restarted in the meantime.
+pub fn capture_done(lua: &Lua, id: u64, surface: Option<&ImageSurface>) -> rlua::Result<()> {
+    let callbacks = lua.named_registry_value::<Table>(CAPTURE_CALLBACKS)?;
+    let callback = match callbacks.get::<_, Option<Function>>(id)? {
+        Some(callback) => callback,
+        None => return Ok(())
+    };
+    callbacks.set(id, Value::Nil)?;
+    callback.call(surface.map_or(Value::Nil, drawable::surface_to_lua))
+}
@@ -2,6 +2,7 @@
 
 use super::object::{self, Object};
 use super::property::Property;
+use super::signal;
 use rlua::{self, AnyUserData, Function, Lua, MetaMethod, Table, ToLua, UserData, UserDataMethods,
            Value};
 use std::convert::From;
@@ -123,6 +124,15 @@ impl<'lua> Class<'lua> {
         meta.set("set_newindex_miss_handler",
                   lua.create_function(set_newindex_miss_handler)?
                      .bind(user_data.clone())?)?;
+        meta.set("connect_signal",
+                  lua.create_function(connect_signal)?
+                     .bind(user_data.clone())?)?;
+        meta.set("disconnect_signal",
+                  lua.create_function(disconnect_signal)?
+                     .bind(user_data.clone())?)?;
+        meta.set("emit_signal",
+                  lua.create_function(emit_signal)?
+                     .bind(user_data.clone())?)?;
         meta.set("__index", meta.clone())?;
         table.set_metatable(Some(meta.clone()));
         user_data.set_user_value(table)?;
@@ -134,6 +144,14 @@ impl<'lua> Class<'lua> {
         self.class.borrow::<ClassState>()
             .map(|class| class.checker.clone())
     }
+
+    /// The signals connected to the class itself, rather than to one of
+    /// its objects.
+    pub fn signals(&self) -> rlua::Result<Table<'lua>> {
+        let table = self.class.get_user_value::<Table>()?;
+        let meta = table.get_metatable().expect("Class had no meta table!");
+        meta.get("signals")
+    }
 }
 
 fn set_index_miss_handler<'lua>(_: &'lua Lua,
@@ -153,6 +171,24 @@ fn set_newindex_miss_handler<'lua>(_: &'lua Lua,
     Ok(())
 }
 
+fn connect_signal<'lua>(lua: &'lua Lua,
+                        (class, name, func): (AnyUserData<'lua>, String, Function<'lua>))
+                        -> rlua::Result<()> {
+    signal::connect_class_signal(lua, class.into(), name, &[func])
+}
+
+fn disconnect_signal<'lua>(lua: &'lua Lua,
+                           (class, name): (AnyUserData<'lua>, String))
+                           -> rlua::Result<()> {
+    signal::disconnect_class_signal(lua, class.into(), name)
+}
+
+fn emit_signal<'lua>(lua: &'lua Lua,
+                     (class, name, args): (AnyUserData<'lua>, String, rlua::MultiValue<'lua>))
+                     -> rlua::Result<()> {
+    signal::emit_class_signal(lua, class.into(), name, args)
+}
+
 pub fn class_setup<'lua>(lua: &'lua Lua, name: &str) -> rlua::Result<Class<'lua>> {
     let class = lua.globals()
                    .get::<_, AnyUserData>(name)
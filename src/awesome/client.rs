@@ -1,34 +1,52 @@
 //! TODO Fill in
-use super::class::{Class, ClassBuilder};
-use super::object::{Object, Objectable};
-use rlua::{self, Lua, Table, ToLua, UserData, Value};
+use super::button;
+use super::capture;
+use super::class::{self, Class, ClassBuilder};
+use super::object::{Object, ObjectBuilder, Objectable};
+use rlua::{self, AnyUserData, Function, Lua, ToLua, UserData, Value};
 use std::default::Default;
 use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use compositor::{self, View};
+
+pub const CLIENTS_HANDLE: &'static str = "__clients";
 
 #[derive(Clone, Debug)]
 pub struct ClientState {
     // TODO Fill in
-    dummy: i32
+    dummy: i32,
+    /// The view shown for this client, if it is still around.
+    pub view: Option<Rc<View>>
 }
 
+unsafe impl Send for ClientState {}
+
 pub struct Client<'lua>(Object<'lua>);
 
 impl Default for ClientState {
     fn default() -> Self {
-        ClientState { dummy: 0 }
+        ClientState { dummy: 0,
+                      view: None }
     }
 }
 
-/* This is currently unused.
- * TODO: Figure out if this will be needed later.
-
-impl <'lua> Client<'lua> {
-    fn new(lua: &Lua) -> rlua::Result<Object> {
+impl<'lua> Client<'lua> {
+    fn new(lua: &'lua Lua, view: Rc<View>) -> rlua::Result<Object<'lua>> {
         let class = class::class_setup(lua, "client")?;
-        Ok(Client::allocate(lua, class)?.build())
+        let mut client = Client::cast(object_setup(lua, Client::allocate(lua, class)?)?.build())?;
+        client.get_object_mut()?.view = Some(view);
+        Ok(client.0)
+    }
+
+    /// Whether this is the client of the view.
+    fn shows(&self, view: &Rc<View>) -> rlua::Result<bool> {
+        Ok(self.state()?
+               .view
+               .as_ref()
+               .map_or(false, |other| Rc::ptr_eq(other, view)))
     }
 }
-*/
 
 impl Display for ClientState {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -44,9 +62,51 @@ impl<'lua> ToLua<'lua> for Client<'lua> {
 
 impl UserData for ClientState {}
 
-pub fn init(lua: &Lua) -> rlua::Result<Class> {
-    method_setup(lua, Class::builder(lua, "client", None)?)?.save_class("client")?
-                                                            .build()
+/// Sets up the client class, with a client for each of the views that are
+/// already shown (e.g when Lua is restarted).
+pub fn init<'lua>(lua: &'lua Lua, views: &[Rc<View>]) -> rlua::Result<Class<'lua>> {
+    let clients: Vec<Client> = Vec::new();
+    lua.set_named_registry_value(CLIENTS_HANDLE, clients.to_lua(lua)?)?;
+    let class = method_setup(lua, Class::builder(lua, "client", None)?)?.save_class("client")?
+                                                                        .build()?;
+    for view in views {
+        manage_client(lua, view.clone())?;
+    }
+    Ok(class)
+}
+
+/// Makes a client for a view that was just mapped.
+pub fn manage_client(lua: &Lua, view: Rc<View>) -> rlua::Result<()> {
+    let mut clients = lua.named_registry_value::<Vec<AnyUserData>>(CLIENTS_HANDLE)?;
+    clients.push(Client::new(lua, view)?.object);
+    lua.set_named_registry_value(CLIENTS_HANDLE, clients.to_lua(lua)?)
+}
+
+/// Forgets the client of a view that was unmapped.
+///
+/// Lua might still hold on to the client, but there's nothing left for it
+/// to show.
+pub fn unmanage_client(lua: &Lua, view: &Rc<View>) -> rlua::Result<()> {
+    let mut clients = Vec::new();
+    for client in lua.named_registry_value::<Vec<AnyUserData>>(CLIENTS_HANDLE)? {
+        let mut client_obj = Client::cast(client.clone().into())?;
+        if client_obj.shows(view)? {
+            client_obj.get_object_mut()?.view = None;
+        } else {
+            clients.push(client);
+        }
+    }
+    lua.set_named_registry_value(CLIENTS_HANDLE, clients.to_lua(lua)?)
+}
+
+/// The client of the view, if Lua knows about it.
+pub fn client_of<'lua>(lua: &'lua Lua, view: &Rc<View>) -> rlua::Result<Option<AnyUserData<'lua>>> {
+    for client in lua.named_registry_value::<Vec<AnyUserData>>(CLIENTS_HANDLE)? {
+        if Client::cast(client.clone().into())?.shows(view)? {
+            return Ok(Some(client))
+        }
+    }
+    Ok(None)
 }
 
 fn method_setup<'lua>(lua: &'lua Lua,
@@ -55,11 +115,43 @@ fn method_setup<'lua>(lua: &'lua Lua,
     // TODO Do properly
     use super::dummy;
     builder.method("connect_signal".into(), lua.create_function(dummy)?)?
-           .method("get".into(), lua.create_function(dummy_table)?)
+           .method("get".into(), lua.create_function(get_clients)?)
+}
+
+fn object_setup<'lua>(lua: &'lua Lua,
+                      builder: ObjectBuilder<'lua>)
+                      -> rlua::Result<ObjectBuilder<'lua>> {
+    let table = lua.create_table()?;
+    table.set("buttons", lua.create_function(button::object_buttons)?)?;
+    table.set("content", lua.create_function(content)?)?;
+    builder.add_to_meta(table)
 }
 
 impl_objectable!(Client, ClientState);
 
-fn dummy_table<'lua>(lua: &'lua Lua, _: rlua::Value) -> rlua::Result<Table<'lua>> {
-    Ok(lua.create_table()?)
+/// Calls `callback` with what the client shows, as a cairo surface, once it
+/// has been drawn, e.g for previews.
+///
+/// It is called with `nil` if the client is gone.
+fn content<'lua>(lua: &'lua Lua,
+                 (object, callback): (AnyUserData<'lua>, Function<'lua>))
+                 -> rlua::Result<()> {
+    let client = Client::cast(object.into())?;
+    let view = match client.state()?.view {
+        Some(ref view) => view.clone(),
+        None => return callback.call(Value::Nil)
+    };
+    capture::capture_view(lua, view, callback)
+}
+
+/// The clients, topmost first.
+// TODO Filter by screen
+fn get_clients<'lua>(lua: &'lua Lua, _: rlua::Value) -> rlua::Result<Vec<AnyUserData<'lua>>> {
+    let mut clients = Vec::new();
+    for view in compositor::views() {
+        if let Some(client) = client_of(lua, &view)? {
+            clients.push(client);
+        }
+    }
+    Ok(clients)
 }
@@ -1,23 +1,34 @@
 //! TODO Fill in
 
+use std::cell::RefMut;
 use std::default::Default;
 use std::fmt::{self, Display, Formatter};
+use std::mem;
 
 use cairo::{Format, ImageSurface};
 use glib::translate::ToGlibPtr;
 use rlua::{self, AnyUserData, LightUserData, Lua, Table, ToLua, UserData, UserDataMethods, Value};
-use wlroots::{Area, Origin, Size};
+use wlroots::{Area, Origin, Size, Texture};
+
+use compositor;
 
 use super::class::{self, Class};
 use super::object::{self, Object, Objectable};
 use super::property::Property;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct DrawableState {
     pub surface: Option<ImageSurface>,
     geo: Area,
-    // TODO Use this to determine whether we draw this or not
-    refreshed: bool
+    /// The scale of the screen the drawable is on.
+    ///
+    /// The surface is this much bigger than the geometry, so that it stays
+    /// sharp on HiDPI screens.
+    scale: f32,
+    /// The surface was drawn to since it was last uploaded to the texture.
+    refreshed: bool,
+    /// The surface as it was last uploaded to the GPU.
+    texture: Option<Texture<'static>>
 }
 
 pub struct Drawable<'lua>(Object<'lua>);
@@ -28,7 +39,17 @@ impl Default for DrawableState {
     fn default() -> Self {
         DrawableState { surface: None,
                         geo: Area::default(),
-                        refreshed: false }
+                        scale: 1.0,
+                        refreshed: false,
+                        texture: None }
+    }
+}
+
+impl Drop for DrawableState {
+    fn drop(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            compositor::free_texture(texture);
+        }
     }
 }
 
@@ -52,47 +73,78 @@ impl<'lua> Drawable<'lua> {
         let drawable = self.state()?;
         Ok(match drawable.surface {
             None => Value::Nil,
-            Some(ref image) => {
-                let stash = image.to_glib_none();
-                let ptr = stash.0;
-                // NOTE
-                // We bump the reference count because now Lua has a reference which
-                // it manages via LGI.
-                //
-                // If there's a bug, worst case scenario there's a memory leak.
-                unsafe {
-                    ::cairo_sys::cairo_surface_reference(ptr);
-                }
-                Value::LightUserData(LightUserData(ptr as _))
-            }
+            Some(ref image) => surface_to_lua(image)
         })
     }
 
-    /// Sets the geometry, and allocates a new surface.
-    pub fn set_geometry(&mut self, geometry: Area) -> rlua::Result<()> {
+    pub fn get_scale(&self) -> rlua::Result<f32> {
+        Ok(self.state()?.scale)
+    }
+
+    /// Sets the geometry and scale, and allocates a new surface if either
+    /// changed the size of it.
+    ///
+    /// Returns whether a new surface was allocated.
+    pub fn set_geometry(&mut self, geometry: Area, scale: f32) -> rlua::Result<bool> {
         use rlua::Error::RuntimeError;
         let mut drawable = self.get_object_mut()?;
-        let size_changed = drawable.geo != geometry;
+        let size_changed = drawable.geo.size != geometry.size || drawable.scale != scale;
         drawable.geo = geometry;
+        drawable.scale = scale;
         if size_changed {
             drawable.refreshed = false;
             drawable.surface = None;
+            if let Some(texture) = drawable.texture.take() {
+                compositor::free_texture(texture);
+            }
             let size: Size = geometry.size;
             if size.width > 0 && size.height > 0 {
-                drawable.surface = Some(ImageSurface::create(Format::ARgb32,
-                                                        size.width,
-                                                        size.height)
-                    .map_err(|err| RuntimeError(format!("Could not allocate {:?}", err)))?);
-                // TODO emity property::surface
+                let width = (size.width as f32 * scale).ceil() as i32;
+                let height = (size.height as f32 * scale).ceil() as i32;
+                let surface = ImageSurface::create(Format::ARgb32, width, height)
+                    .map_err(|err| RuntimeError(format!("Could not allocate {:?}", err)))?;
+                // Lua draws in the same coordinates no matter the scale.
+                surface.set_device_scale(scale as f64, scale as f64);
+                drawable.surface = Some(surface);
             }
         }
-        Ok(())
+        Ok(size_changed)
     }
 
     /// Signals that the drawable's surface was updated.
     pub fn refresh(&mut self) -> rlua::Result<()> {
         let mut drawable = self.get_object_mut()?;
         drawable.refreshed = true;
+        compositor::damage_area(drawable.geo);
+        Ok(())
+    }
+
+    /// Gets the texture to render the drawable with.
+    ///
+    /// The surface is only uploaded again, using `upload`, if it was
+    /// refreshed or resized since the last time.
+    pub fn texture<F>(&mut self, upload: F) -> rlua::Result<RefMut<Option<Texture<'static>>>>
+        where F: FnOnce(&mut ImageSurface) -> Option<Texture<'static>>
+    {
+        let mut drawable = self.get_object_mut()?;
+        if drawable.refreshed || drawable.texture.is_none() {
+            let texture = match drawable.surface {
+                Some(ref mut surface) => upload(surface),
+                None => None
+            };
+            if let Some(texture) = mem::replace(&mut drawable.texture, texture) {
+                compositor::free_texture(texture);
+            }
+            drawable.refreshed = false;
+        }
+        Ok(RefMut::map(drawable, |drawable| &mut drawable.texture))
+    }
+
+    /// Frees the texture, e.g because the drawable is no longer visible.
+    pub fn release_texture(&mut self) -> rlua::Result<()> {
+        if let Some(texture) = self.get_object_mut()?.texture.take() {
+            compositor::free_texture(texture);
+        }
         Ok(())
     }
 }
@@ -122,15 +174,39 @@ pub fn init(lua: &Lua) -> rlua::Result<Class> {
                                 None,
                                 Some(lua.create_function(get_surface)?),
                                 None))?
+        .property(Property::new("scale".into(),
+                                None,
+                                Some(lua.create_function(get_scale)?),
+                                None))?
         .save_class("drawable")?
         .build()
 }
 
+/// Gives Lua a cairo surface, as a light user data that LGI understands.
+pub fn surface_to_lua<'lua>(surface: &ImageSurface) -> Value<'lua> {
+    let stash = surface.to_glib_none();
+    let ptr = stash.0;
+    // NOTE
+    // We bump the reference count because now Lua has a reference which
+    // it manages via LGI.
+    //
+    // If there's a bug, worst case scenario there's a memory leak.
+    unsafe {
+        ::cairo_sys::cairo_surface_reference(ptr);
+    }
+    Value::LightUserData(LightUserData(ptr as _))
+}
+
 fn get_surface<'lua>(_: &'lua Lua, obj: AnyUserData<'lua>) -> rlua::Result<Value<'lua>> {
     let drawable = Drawable::cast(obj.into())?;
     drawable.get_surface()
 }
 
+fn get_scale<'lua>(_: &'lua Lua, obj: AnyUserData<'lua>) -> rlua::Result<f32> {
+    let drawable = Drawable::cast(obj.into())?;
+    drawable.get_scale()
+}
+
 fn geometry<'lua>(lua: &'lua Lua, obj: AnyUserData<'lua>) -> rlua::Result<Table<'lua>> {
     let drawable = Drawable::cast(obj.into())?;
     let geometry = drawable.get_geometry()?;
@@ -2,17 +2,18 @@
 // drawable a lua object
 use std::fmt::{self, Display, Formatter};
 
-use std::cell::RefMut;
-
 use cairo::ImageSurface;
 use rlua::prelude::LuaInteger;
 use rlua::{self, AnyUserData, Lua, Table, ToLua, UserData, UserDataMethods, Value};
-use wlroots::{Area, Origin, Size, Texture};
+use wlroots::{Area, Origin, Size};
+
+use compositor;
 
 use super::class::{self, Class, ClassBuilder};
 use super::drawable::Drawable;
 use super::object::{self, Object, ObjectBuilder, Objectable};
 use super::property::Property;
+use super::{button, screen, signal};
 
 pub const DRAWINS_HANDLE: &'static str = "__drawins";
 
@@ -25,7 +26,6 @@ pub struct DrawinState {
     cursor: String,
     geometry: Area,
     geometry_dirty: bool,
-    texture: Option<Texture<'static>>,
     surface: Option<ImageSurface>
 }
 
@@ -67,21 +67,26 @@ impl<'lua> Drawin<'lua> {
         Drawable::cast(table.get::<_, AnyUserData>("drawable")?.into())
     }
 
-    pub fn texture(&mut self) -> rlua::Result<RefMut<Option<Texture<'static>>>> {
-        Ok(RefMut::map(self.get_object_mut()?, |state| &mut state.texture))
-    }
-
-    fn update_drawing(&mut self) -> rlua::Result<()> {
+    fn update_drawing(&mut self, lua: &Lua) -> rlua::Result<()> {
         let table = self.0.table()?;
         let user_data: AnyUserData = table.get::<_, AnyUserData>("drawable")?.clone();
-        let mut drawable = Drawable::cast(user_data.into())?;
-        let mut state = self.get_object_mut()?;
-        if state.geometry_dirty {
-            drawable.set_geometry(state.geometry)?;
-            state.geometry_dirty = false;
-        }
-        state.surface = drawable.state()?.surface.clone();
+        let mut drawable = Drawable::cast(user_data.clone().into())?;
+        let new_surface = {
+            let mut state = self.get_object_mut()?;
+            let scale = screen::scale_at(lua, state.geometry.origin)?;
+            let mut new_surface = false;
+            if state.geometry_dirty || drawable.get_scale()? != scale {
+                new_surface = drawable.set_geometry(state.geometry, scale)?;
+                state.geometry_dirty = false;
+            }
+            state.surface = drawable.state()?.surface.clone();
+            new_surface
+        };
         table.raw_set("drawable", drawable)?;
+        if new_surface {
+            // Lua has to draw on the new surface.
+            signal::emit_object_signal(lua, user_data.into(), "property::surface".into(), ())?;
+        }
         Ok(())
     }
 
@@ -90,34 +95,34 @@ impl<'lua> Drawin<'lua> {
         Ok(drawin.visible)
     }
 
-    fn set_visible(&mut self, val: bool) -> rlua::Result<()> {
+    fn set_visible(&mut self, lua: &Lua, val: bool) -> rlua::Result<()> {
         {
             let mut drawin = self.get_object_mut()?;
             drawin.visible = val;
+            compositor::damage_area(drawin.geometry);
         }
         if val {
-            self.map()
+            self.map(lua)
         } else {
             self.unmap()
         }
     }
 
-    fn map(&mut self) -> rlua::Result<()> {
+    fn map(&mut self, lua: &Lua) -> rlua::Result<()> {
         // TODO other things
-        self.update_drawing()?;
+        self.update_drawing(lua)?;
         Ok(())
     }
 
     fn unmap(&mut self) -> rlua::Result<()> {
-        // TODO?
-        Ok(())
+        self.drawable()?.release_texture()
     }
 
     pub fn get_geometry(&self) -> rlua::Result<Area> {
         Ok(self.state()?.geometry)
     }
 
-    fn resize(&mut self, geometry: Area) -> rlua::Result<()> {
+    fn resize(&mut self, lua: &Lua, geometry: Area) -> rlua::Result<()> {
         {
             let mut state = self.get_object_mut()?;
             let old_geometry = state.geometry;
@@ -133,11 +138,42 @@ impl<'lua> Drawin<'lua> {
                 }
             }
             state.geometry_dirty = true;
+            if state.visible {
+                compositor::damage_area(old_geometry);
+                compositor::damage_area(state.geometry);
+            }
             // TODO emit signals
             // TODO update screen workareas like in awesome? Might not be necessary
         }
-        self.update_drawing()
+        self.update_drawing(lua)
+    }
+}
+
+/// The visible drawin at a point in output layout coordinates, if there is
+/// one.
+///
+/// Drawins later in the list are drawn above the earlier ones, so they are
+/// found first.
+pub fn drawin_at<'lua>(lua: &'lua Lua, x: f64, y: f64) -> rlua::Result<Option<AnyUserData<'lua>>> {
+    let drawins = lua.named_registry_value::<Vec<AnyUserData>>(DRAWINS_HANDLE)?;
+    let point = Area::new(Origin::new(x as i32, y as i32), Size::new(1, 1));
+    for drawin in drawins.into_iter().rev() {
+        let mut cast = Drawin::cast(drawin.clone().into())?;
+        if cast.get_visible()? && compositor::intersection(cast.get_geometry()?, point).is_some() {
+            return Ok(Some(drawin))
+        }
     }
+    Ok(None)
+}
+
+/// Reallocates the surfaces of the drawins that are now on a screen with a
+/// different scale.
+pub fn update_scales(lua: &Lua) -> rlua::Result<()> {
+    let drawins = lua.named_registry_value::<Vec<AnyUserData>>(DRAWINS_HANDLE)?;
+    for drawin in drawins {
+        Drawin::cast(drawin.into())?.update_drawing(lua)?;
+    }
+    Ok(())
 }
 
 impl<'lua> ToLua<'lua> for Drawin<'lua> {
@@ -201,13 +237,15 @@ fn object_setup<'lua>(lua: &'lua Lua,
     table.set("drawable", drawable_table)?;
     table.set("geometry", lua.create_function(drawin_geometry)?)?;
     table.set("struts", lua.create_function(drawin_struts)?)?;
-    table.set("buttons", lua.create_function(super::dummy)?)?;
+    table.set("buttons", lua.create_function(button::object_buttons)?)?;
     builder.add_to_meta(table)
 }
 
-fn set_visible<'lua>(_: &'lua Lua, (obj, visible): (AnyUserData<'lua>, bool)) -> rlua::Result<()> {
+fn set_visible<'lua>(lua: &'lua Lua,
+                     (obj, visible): (AnyUserData<'lua>, bool))
+                     -> rlua::Result<()> {
     let mut drawin = Drawin::cast(obj.into())?;
-    drawin.set_visible(visible)
+    drawin.set_visible(lua, visible)
     // TODO signal
 }
 
@@ -228,7 +266,7 @@ fn drawin_geometry<'lua>(lua: &'lua Lua,
         let y = geometry.get::<_, i32>("y")?;
         if width > 0 && height > 0 {
             let geo = Area::new(Origin { x, y }, Size { width, height });
-            drawin.resize(geo)?;
+            drawin.resize(lua, geo)?;
         }
     }
     let new_geo = drawin.get_geometry()?;
@@ -248,11 +286,11 @@ fn get_x<'lua>(_: &'lua Lua, drawin: AnyUserData<'lua>) -> rlua::Result<LuaInteg
     Ok(x as LuaInteger)
 }
 
-fn set_x<'lua>(_: &'lua Lua, (drawin, x): (AnyUserData<'lua>, LuaInteger)) -> rlua::Result<()> {
+fn set_x<'lua>(lua: &'lua Lua, (drawin, x): (AnyUserData<'lua>, LuaInteger)) -> rlua::Result<()> {
     let mut drawin = Drawin::cast(drawin.into())?;
     let mut geo = drawin.get_geometry()?;
     geo.origin.x = x as i32;
-    drawin.resize(geo)?;
+    drawin.resize(lua, geo)?;
     Ok(())
 }
 
@@ -262,11 +300,11 @@ fn get_y<'lua>(_: &'lua Lua, drawin: AnyUserData<'lua>) -> rlua::Result<LuaInteg
     Ok(y as LuaInteger)
 }
 
-fn set_y<'lua>(_: &'lua Lua, (drawin, y): (AnyUserData<'lua>, LuaInteger)) -> rlua::Result<()> {
+fn set_y<'lua>(lua: &'lua Lua, (drawin, y): (AnyUserData<'lua>, LuaInteger)) -> rlua::Result<()> {
     let mut drawin = Drawin::cast(drawin.into())?;
     let mut geo = drawin.get_geometry()?;
     geo.origin.y = y as i32;
-    drawin.resize(geo)?;
+    drawin.resize(lua, geo)?;
     Ok(())
 }
 
@@ -276,14 +314,14 @@ fn get_width<'lua>(_: &'lua Lua, drawin: AnyUserData<'lua>) -> rlua::Result<LuaI
     Ok(width as LuaInteger)
 }
 
-fn set_width<'lua>(_: &'lua Lua,
+fn set_width<'lua>(lua: &'lua Lua,
                    (drawin, width): (AnyUserData<'lua>, LuaInteger))
                    -> rlua::Result<()> {
     let mut drawin = Drawin::cast(drawin.into())?;
     let mut geo = drawin.get_geometry()?;
     if width > 0 {
         geo.size.width = width as i32;
-        drawin.resize(geo)?;
+        drawin.resize(lua, geo)?;
     }
     Ok(())
 }
@@ -294,14 +332,14 @@ fn get_height<'lua>(_: &'lua Lua, drawin: AnyUserData<'lua>) -> rlua::Result<Lua
     Ok(height as LuaInteger)
 }
 
-fn set_height<'lua>(_: &'lua Lua,
+fn set_height<'lua>(lua: &'lua Lua,
                     (drawin, height): (AnyUserData<'lua>, LuaInteger))
                     -> rlua::Result<()> {
     let mut drawin = Drawin::cast(drawin.into())?;
     let mut geo = drawin.get_geometry()?;
     if height > 0 {
         geo.size.height = height as i32;
-        drawin.resize(geo)?;
+        drawin.resize(lua, geo)?;
     }
     Ok(())
 }
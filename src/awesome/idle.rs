@@ -0,0 +1,64 @@
+//! What happens when the user is idle, configured from Lua.
+//!
+//! ```lua
+//! idle.set_timeouts { 300, 600 }
+//! idle.set_power_off(900)
+//! awesome.connect_signal("idle::start", function(timeout) ... end)
+//! awesome.connect_signal("idle::stop", function() ... end)
+//! ```
+//!
+//! `idle::start` is emitted with the timeout (in seconds) once each of the
+//! timeouts passed without any activity, and `idle::stop` at the next
+//! activity. The outputs are turned off after the power off timeout, and
+//! back on at the next activity.
+
+use std::time::Duration;
+
+use rlua::{self, Lua};
+
+use compositor;
+
+pub const IDLE_TABLE: &str = "idle";
+
+/// Init the methods defined on this interface.
+pub fn init(lua: &Lua) -> rlua::Result<()> {
+    let idle_table = lua.create_table()?;
+    idle_table.set("set_timeouts", lua.create_function(set_timeouts)?)?;
+    idle_table.set("set_power_off", lua.create_function(set_power_off)?)?;
+    idle_table.set("inhibited", lua.create_function(inhibited)?)?;
+    let globals = lua.globals();
+    globals.set(IDLE_TABLE, idle_table)
+}
+
+/// Sets after how many seconds of inactivity `idle::start` is emitted.
+fn set_timeouts<'lua>(_: &'lua Lua, timeouts: Vec<f64>) -> rlua::Result<()> {
+    let timeouts = timeouts.into_iter()
+                           .map(duration_from_secs)
+                           .collect::<rlua::Result<_>>()?;
+    compositor::set_idle_timeouts(timeouts);
+    Ok(())
+}
+
+/// Sets after how many seconds of inactivity the outputs are turned off.
+///
+/// If `nil` they are never turned off.
+fn set_power_off<'lua>(_: &'lua Lua, timeout: Option<f64>) -> rlua::Result<()> {
+    let timeout = match timeout {
+        Some(timeout) => Some(duration_from_secs(timeout)?),
+        None => None
+    };
+    compositor::set_power_off_timeout(timeout);
+    Ok(())
+}
+
+/// Whether a client (e.g a video player) keeps the user from becoming idle.
+fn inhibited<'lua>(_: &'lua Lua, _: ()) -> rlua::Result<bool> {
+    Ok(compositor::idle_inhibited())
+}
+
+fn duration_from_secs(secs: f64) -> rlua::Result<Duration> {
+    if !(secs > 0.0) {
+        return Err(rlua::Error::RuntimeError(format!("Invalid timeout {}", secs)))
+    }
+    Ok(Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32))
+}
@@ -5,35 +5,44 @@ use wlroots;
 use awesome::lua::setup_lua;
 use rlua::{self, LightUserData, Lua, Table};
 use std::{env, mem, path::PathBuf};
-use xcb::{xkb, Connection};
+use xcb::{ffi::xcb_connection_t, xkb, Connection};
 
 mod awesome;
 mod button;
+mod capture;
 mod class;
 mod client;
 mod drawable;
 mod drawin;
+mod idle;
 mod key;
 pub mod keygrabber;
 pub mod lua;
 mod mouse;
 pub mod mousegrabber;
 mod object;
+mod output;
 mod property;
 mod root;
 mod screen;
+mod selection;
 pub mod signal;
 mod tag;
 mod xproperty;
 
 pub use self::lua::{LUA, NEXT_LUA};
 
+pub use self::button::{emit_button_bindings, ButtonPress};
+pub use self::capture::capture_done;
+pub use self::client::{manage_client, unmanage_client};
 pub use self::drawin::{Drawin, DRAWINS_HANDLE};
 pub use self::key::Key;
 pub use self::keygrabber::keygrabber_handle;
 pub use self::mousegrabber::mousegrabber_handle;
 pub use self::object::{Object, Objectable};
+pub use self::output::choose_output_profile;
 pub use self::root::ROOT_KEYS_HANDLE;
+pub use self::screen::{update_screens, update_workarea};
 pub use self::signal::*;
 
 use compositor::Server;
@@ -57,6 +66,11 @@ pub extern "C" fn refresh_awesome() {
                                        }
                                    });
                           let compositor = wlroots::compositor_handle().unwrap();
+                          // The drawins of the old Lua thread are gone.
+                          with_handles!([(compositor: {compositor.clone()})] => {
+                              let server: &mut Server = compositor.into();
+                              server.damage.add_whole();
+                          }).unwrap();
                           setup_lua(compositor);
                       }
                   });
@@ -65,11 +79,16 @@ pub extern "C" fn refresh_awesome() {
 pub fn init(lua: &Lua, server: &mut Server) -> rlua::Result<()> {
     setup_awesome_path(lua)?;
     setup_global_signals(lua)?;
-    setup_xcb_connection(lua)?;
+    // NOTE Connecting before XWayland is ready would block forever, because
+    // it can't finish starting up until we return to the event loop.
+    if server.xwayland_ready {
+        setup_xcb_connection(lua)?;
+    }
     button::init(lua)?;
+    capture::init(lua)?;
     awesome::init(lua)?;
     key::init(lua)?;
-    client::init(lua)?;
+    client::init(lua, &server.views)?;
     screen::init(lua, server)?;
     keygrabber::init(lua)?;
     root::init(lua)?;
@@ -78,6 +97,9 @@ pub fn init(lua: &Lua, server: &mut Server) -> rlua::Result<()> {
     drawin::init(lua)?;
     drawable::init(lua)?;
     mousegrabber::init(lua)?;
+    output::init(lua)?;
+    idle::init(lua)?;
+    selection::init(lua)?;
     Ok(())
 }
 
@@ -121,13 +143,15 @@ fn setup_global_signals(lua: &Lua) -> rlua::Result<()> {
 }
 
 /// Sets up the xcb connection and stores it in Lua (for us to access it later)
-fn setup_xcb_connection(lua: &Lua) -> rlua::Result<()> {
+///
+/// If XWayland can't be reached the connection is left unset, and the
+/// functions that need it degrade gracefully (see `xcb_connection`).
+pub fn setup_xcb_connection(lua: &Lua) -> rlua::Result<()> {
     let con = match Connection::connect(None) {
         Err(err) => {
-            error!("Way Cooler requires XWayland in order to function");
-            error!("However, xcb could not connect to it. Is it running?");
-            error!("{:?}", err);
-            panic!("Could not connect to XWayland instance");
+            warn!("Could not connect to XWayland, X11 specific functions will not work");
+            warn!("{:?}", err);
+            return Ok(())
         }
         Ok(con) => con.0
     };
@@ -135,11 +159,13 @@ fn setup_xcb_connection(lua: &Lua) -> rlua::Result<()> {
     match xkb::use_extension(&con, 1, 0).get_reply() {
         Ok(r) => {
             if !r.supported() {
-                panic!("xkb-1.0 is not supported");
+                warn!("xkb-1.0 is not supported by XWayland");
+                return Ok(())
             }
         }
         Err(err) => {
-            panic!("Could not get xkb extension supported version {:?}", err);
+            warn!("Could not get xkb extension supported version {:?}", err);
+            return Ok(())
         }
     }
     lua.set_named_registry_value(XCB_CONNECTION_HANDLE,
@@ -148,6 +174,12 @@ fn setup_xcb_connection(lua: &Lua) -> rlua::Result<()> {
     Ok(())
 }
 
+/// Gets the raw xcb connection to XWayland, if there is one.
+pub fn xcb_connection(lua: &Lua) -> rlua::Result<Option<*mut xcb_connection_t>> {
+    let con = lua.named_registry_value::<Option<LightUserData>>(XCB_CONNECTION_HANDLE)?;
+    Ok(con.map(|con| con.0 as _))
+}
+
 pub fn dummy<'lua>(_: &'lua Lua, _: rlua::Value) -> rlua::Result<()> {
     Ok(())
 }
@@ -0,0 +1,168 @@
+//! Configuration of the outputs (e.g their resolution) from Lua.
+//!
+//! The global `output` table is keyed by the name of the output:
+//!
+//! ```lua
+//! output["DP-1"] = { mode = { width = 1920, height = 1080, refresh = 60 },
+//!                    scale = 2,
+//!                    transform = "90",
+//!                    position = { x = 0, y = 0 } }
+//! output["HDMI-A-1"] = { enabled = false }
+//! ```
+//!
+//! Outputs that aren't plugged in yet are set up once they are.
+//!
+//! How the outputs are arranged can also be saved as a profile with
+//! `output.save_profile(name)`, which is applied automatically when the same
+//! outputs are connected again. `output.set_profile_handler(f)` overrides
+//! which profile that is: `f` is called with the connected outputs and the
+//! name of the matching profile (if any), and returns the name of the profile
+//! to apply, `nil` to keep the match or `false` to not apply any.
+//!
+//! Profiles only change how the outputs are set up, not what is in the
+//! `output` table.
+
+use rlua::{self, FromLua, Function, Lua, Table, Value};
+use wlroots::{Origin, OutputTransform};
+
+use awesome::screen;
+use compositor::{self, ModeConfig, OutputConfig, OutputIdentity};
+
+pub const OUTPUT_TABLE: &str = "output";
+const OUTPUT_CONFIGS: &str = "__output_configs";
+const PROFILE_HANDLER: &str = "__output_profile_handler";
+
+/// Init the methods defined on this interface.
+pub fn init(lua: &Lua) -> rlua::Result<()> {
+    let output_table = lua.create_table()?;
+    let meta = lua.create_table()?;
+    meta.set("__index", lua.create_function(index)?)?;
+    meta.set("__newindex", lua.create_function(new_index)?)?;
+    output_table.set("save_profile", lua.create_function(save_profile)?)?;
+    output_table.set("load_profile", lua.create_function(load_profile)?)?;
+    output_table.set("profiles", lua.create_function(profiles)?)?;
+    output_table.set("set_profile_handler", lua.create_function(set_profile_handler)?)?;
+    output_table.set_metatable(Some(meta));
+    lua.set_named_registry_value(OUTPUT_CONFIGS, lua.create_table()?)?;
+    let globals = lua.globals();
+    globals.set(OUTPUT_TABLE, output_table)
+}
+
+/// Changes the configuration of the output with the given name and updates
+/// the screens to match.
+///
+/// If `replace` is false only what is set in `config` is changed.
+pub fn configure(lua: &Lua, name: &str, config: OutputConfig, replace: bool) -> rlua::Result<()> {
+    let outputs = compositor::configure_output(name, config, replace);
+    screen::update_screens(lua, &outputs)
+}
+
+/// Lets the Lua profile handler pick which output profile to apply.
+///
+/// `matching` is the profile saved for the connected outputs, which is
+/// applied if there is no handler.
+pub fn choose_output_profile(lua: &Lua,
+                             connected: &[OutputIdentity],
+                             matching: Option<String>)
+                             -> rlua::Result<Option<String>> {
+    let handler = match lua.named_registry_value::<Value>(PROFILE_HANDLER)? {
+        Value::Function(handler) => handler,
+        _ => return Ok(matching)
+    };
+    let outputs = lua.create_table()?;
+    for (index, identity) in connected.iter().enumerate() {
+        let output = lua.create_table()?;
+        output.set("name", identity.name.as_str())?;
+        output.set("make", identity.make.as_str())?;
+        output.set("model", identity.model.as_str())?;
+        output.set("serial", identity.serial.as_str())?;
+        outputs.set(index + 1, output)?;
+    }
+    match handler.call::<_, Value>((outputs, matching.clone()))? {
+        Value::Nil => Ok(matching),
+        Value::Boolean(false) => Ok(None),
+        value => Ok(Some(String::from_lua(value, lua)?))
+    }
+}
+
+/// Parses a mode table, e.g `{ width = 1920, height = 1080, refresh = 60 }`.
+///
+/// The refresh rate is in Hz, and is optional.
+pub fn mode_from_table(table: Table) -> rlua::Result<ModeConfig> {
+    let refresh = table.get::<_, Option<f64>>("refresh")?;
+    Ok(ModeConfig { width: table.get("width")?,
+                    height: table.get("height")?,
+                    refresh: refresh.map(|refresh| (refresh * 1000.0).round() as i32) })
+}
+
+/// Parses a position table, e.g `{ x = 1920, y = 0 }`.
+pub fn position_from_table(table: Table) -> rlua::Result<Origin> {
+    Ok(Origin::new(table.get("x")?, table.get("y")?))
+}
+
+/// Parses an output transform, e.g "90" or "flipped-180".
+pub fn transform_from_value(value: Value) -> rlua::Result<OutputTransform> {
+    let name = match value {
+        Value::String(name) => name.to_str()?.to_string(),
+        Value::Integer(degrees) => degrees.to_string(),
+        Value::Number(degrees) => (degrees as i64).to_string(),
+        _ => return Err(rlua::Error::RuntimeError("Output transform must be a string".into()))
+    };
+    compositor::transform_from_name(&name).map_err(rlua::Error::RuntimeError)
+}
+
+fn config_from_table(table: Table) -> rlua::Result<OutputConfig> {
+    let mode = match table.get::<_, Option<Table>>("mode")? {
+        Some(mode) => Some(mode_from_table(mode)?),
+        None => None
+    };
+    let transform = match table.get::<_, Value>("transform")? {
+        Value::Nil => None,
+        value => Some(transform_from_value(value)?)
+    };
+    let position = match table.get::<_, Option<Table>>("position")? {
+        Some(position) => Some(position_from_table(position)?),
+        None => None
+    };
+    Ok(OutputConfig { enabled: table.get("enabled")?,
+                      mode,
+                      scale: table.get("scale")?,
+                      transform,
+                      position })
+}
+
+fn index<'lua>(lua: &'lua Lua, (_, name): (Table<'lua>, String)) -> rlua::Result<Value<'lua>> {
+    let configs = lua.named_registry_value::<Table>(OUTPUT_CONFIGS)?;
+    configs.get(name)
+}
+
+fn new_index<'lua>(lua: &'lua Lua,
+                   (_, name, table): (Table<'lua>, String, Option<Table<'lua>>))
+                   -> rlua::Result<()> {
+    let config = match table.clone() {
+        Some(table) => config_from_table(table)?,
+        None => OutputConfig::default()
+    };
+    let configs = lua.named_registry_value::<Table>(OUTPUT_CONFIGS)?;
+    configs.set(name.as_str(), table)?;
+    configure(lua, &name, config, true)
+}
+
+fn save_profile(_: &Lua, name: String) -> rlua::Result<()> {
+    compositor::save_output_profile(&name).map_err(|err| {
+        rlua::Error::RuntimeError(format!("Could not save output profile: {}", err))
+    })
+}
+
+fn load_profile(lua: &Lua, name: String) -> rlua::Result<()> {
+    let outputs = compositor::load_output_profile(&name).map_err(rlua::Error::RuntimeError)?;
+    screen::update_screens(lua, &outputs)
+}
+
+fn profiles(_: &Lua, _: ()) -> rlua::Result<Vec<String>> {
+    Ok(compositor::output_profile_names())
+}
+
+fn set_profile_handler(lua: &Lua, handler: Option<Function>) -> rlua::Result<()> {
+    lua.set_named_registry_value(PROFILE_HANDLER, handler)
+}
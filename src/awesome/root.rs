@@ -1,15 +1,23 @@
 //! TODO Fill in
 
+use super::capture;
 use super::class::{Class, ClassBuilder};
 use super::object::{self, Object, Objectable};
+use super::signal;
 use cairo_sys::cairo_pattern_t;
-use rlua::{self, LightUserData, Lua, Table, ToLua, UserData, UserDataMethods, Value};
+use rlua::{self, Function, LightUserData, Lua, Table, ToLua, UserData, UserDataMethods, Value};
 use std::default::Default;
 use std::fmt::{self, Display, Formatter};
+use wlroots::Size;
+
+use compositor;
 
 /// Handle to the list of global key bindings
 pub const ROOT_KEYS_HANDLE: &'static str = "__ROOT_KEYS";
 
+/// Handle to the list of button bindings of the root window
+pub const ROOT_BUTTONS_HANDLE: &'static str = "__ROOT_BUTTONS";
+
 #[derive(Clone, Debug)]
 pub struct RootState {
     // TODO Fill in
@@ -54,38 +62,111 @@ fn method_setup<'lua>(lua: &'lua Lua,
     // TODO Do properly
     use super::dummy;
     builder.method("connect_signal".into(), lua.create_function(dummy)?)?
-           .method("buttons".into(), lua.create_function(dummy)?)?
+           .method("buttons".into(), lua.create_function(root_buttons)?)?
            .method("wallpaper".into(), lua.create_function(wallpaper)?)?
+           .method("background_color".into(), lua.create_function(background_color)?)?
            .method("tags".into(), lua.create_function(tags)?)?
            .method("keys".into(), lua.create_function(root_keys)?)?
-           .method("size".into(), lua.create_function(dummy_double)?)?
-           .method("size_mm".into(), lua.create_function(dummy_double)?)?
-           .method("cursor".into(), lua.create_function(dummy)?)
+           .method("size".into(), lua.create_function(size)?)?
+           .method("size_mm".into(), lua.create_function(size_mm)?)?
+           .method("cursor".into(), lua.create_function(cursor)?)?
+           .method("cursor_theme".into(), lua.create_function(cursor_theme)?)?
+           .method("content".into(), lua.create_function(content)?)
 }
 
 impl_objectable!(Root, RootState);
 
-fn dummy_double<'lua>(_: &'lua Lua, _: rlua::Value) -> rlua::Result<(i32, i32)> {
-    Ok((0, 0))
+/// The size of the root window, i.e the bounding box of all of the outputs.
+fn size<'lua>(_: &'lua Lua, _: ()) -> rlua::Result<(i32, i32)> {
+    let (Size { width, height }, _) = compositor::root_size();
+    Ok((width, height))
+}
+
+/// The physical size of the root window, in millimetres.
+fn size_mm<'lua>(_: &'lua Lua, _: ()) -> rlua::Result<(i32, i32)> {
+    let (_, Size { width, height }) = compositor::root_size();
+    Ok((width, height))
+}
+
+/// Sets the cursor shown when the pointer isn't over a client, e.g "left_ptr".
+fn cursor<'lua>(_: &'lua Lua, name: String) -> rlua::Result<()> {
+    compositor::set_default_cursor(name);
+    Ok(())
+}
+
+/// Calls `callback` with what is shown on all of the outputs, as a cairo
+/// surface, once they have drawn their next frame.
+fn content<'lua>(lua: &'lua Lua, callback: Function<'lua>) -> rlua::Result<()> {
+    capture::capture_root(lua, callback)
 }
 
 /// Gets the wallpaper as a cairo surface or set it as a cairo pattern
 fn wallpaper<'lua>(lua: &'lua Lua, pattern: Option<LightUserData>) -> rlua::Result<Value<'lua>> {
-    // TODO FIXME Implement for realz
     if let Some(pattern) = pattern {
-        // TODO Wrap before giving it to set_wallpaper
         let pattern = pattern.0 as *mut cairo_pattern_t;
         return set_wallpaper(lua, pattern)?.to_lua(lua)
     }
-    // TODO Look it up in global conf (e.g probably super secret lua value)
-    return Ok(Value::Nil)
+    // NOTE Lua gets its own reference to the surface, which it manages via
+    // LGI.
+    match compositor::wallpaper_surface() {
+        Some(surface) => Ok(Value::LightUserData(LightUserData(surface as _))),
+        None => Ok(Value::Nil)
+    }
 }
 
-fn set_wallpaper<'lua>(_: &'lua Lua, _pattern: *mut cairo_pattern_t) -> rlua::Result<bool> {
-    warn!("Fake setting the wallpaper");
+fn set_wallpaper<'lua>(lua: &'lua Lua, pattern: *mut cairo_pattern_t) -> rlua::Result<bool> {
+    if pattern.is_null() {
+        return Ok(false)
+    }
+    // NOTE This is safe because LGI gives us a valid pattern, and we take
+    // our own reference to it.
+    if let Err(err) = unsafe { compositor::set_wallpaper(pattern) } {
+        warn!("Could not set the wallpaper: {}", err);
+        return Ok(false)
+    }
+    signal::global_emit_signal(lua, ("wallpaper_changed".into(), Value::Nil))?;
     Ok(true)
 }
 
+/// Sets the colour drawn where there is no wallpaper, e.g "#1a1a1a".
+fn background_color<'lua>(_: &'lua Lua, color: String) -> rlua::Result<()> {
+    let color = parse_color(&color).ok_or_else(|| {
+        rlua::Error::RuntimeError(format!("Invalid colour \"{}\", expected e.g \"#1a1a1a\"",
+                                          color))
+    })?;
+    compositor::set_background(color);
+    Ok(())
+}
+
+/// Parses a colour of the form "#rrggbb" or "#rrggbbaa".
+fn parse_color(color: &str) -> Option<[f32; 4]> {
+    if !color.starts_with('#') || !(color.len() == 7 || color.len() == 9) {
+        return None
+    }
+    let channel = |index: usize| {
+        color.get(index..index + 2)
+             .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+             .map(|value| value as f32 / 255.0)
+    };
+    let alpha = if color.len() == 9 { channel(7)? } else { 1.0 };
+    Some([channel(1)?, channel(3)?, channel(5)?, alpha])
+}
+
+/// Gets or sets the xcursor theme and size, e.g `root.cursor_theme("Adwaita", 32)`.
+///
+/// If the size is left out the current one is kept.
+fn cursor_theme<'lua>(_: &'lua Lua,
+                      (name, size): (Option<String>, Option<u32>))
+                      -> rlua::Result<(String, u32)> {
+    let mut theme = compositor::cursor_theme();
+    if let Some(name) = name {
+        theme.name = name;
+        theme.size = size.unwrap_or(theme.size);
+        compositor::set_cursor_theme(theme.clone()).map_err(rlua::Error::RuntimeError)?;
+    }
+    Ok((theme.name, theme.size))
+}
+
 fn tags<'lua>(lua: &'lua Lua, _: ()) -> rlua::Result<Table<'lua>> {
     let table = lua.create_table()?;
     let activated_tags = lua.named_registry_value::<Table>(super::tag::TAG_LIST)?;
@@ -100,22 +181,40 @@ fn tags<'lua>(lua: &'lua Lua, _: ()) -> rlua::Result<Table<'lua>> {
 ///
 /// These bindings will be available when you press keys on the root window.
 fn root_keys<'lua>(lua: &'lua Lua, key_array: rlua::Value<'lua>) -> rlua::Result<rlua::Value<'lua>> {
-    match key_array {
-        // Set the global keys
-        Value::Table(key_array) => {
+    root_bindings(lua, ROOT_KEYS_HANDLE, "keys", key_array)
+}
+
+/// Get or set the button bindings of the root window.
+///
+/// These bindings are used when you click or scroll where there is no
+/// client or drawin.
+fn root_buttons<'lua>(lua: &'lua Lua,
+                      button_array: rlua::Value<'lua>)
+                      -> rlua::Result<rlua::Value<'lua>> {
+    root_bindings(lua, ROOT_BUTTONS_HANDLE, "buttons", button_array)
+}
+
+fn root_bindings<'lua>(lua: &'lua Lua,
+                       handle: &str,
+                       kind: &str,
+                       array: rlua::Value<'lua>)
+                       -> rlua::Result<rlua::Value<'lua>> {
+    match array {
+        // Set the bindings
+        Value::Table(array) => {
             let copy = lua.create_table()?;
             // NOTE We make a deep clone so they can't modify references.
-            for entry in key_array.clone().pairs() {
+            for entry in array.clone().pairs() {
                 let (key, value) = entry?;
                 copy.set::<Value, Value>(key, value)?;
             }
-            lua.set_named_registry_value(ROOT_KEYS_HANDLE, copy)?;
-            Ok(Value::Table(key_array))
+            lua.set_named_registry_value(handle, copy)?;
+            Ok(Value::Table(array))
         }
-        // Get the global keys
+        // Get the bindings
         Value::Nil => {
             let res = lua.create_table()?;
-            for entry in lua.named_registry_value::<Table>(ROOT_KEYS_HANDLE).or(lua.create_table())?.pairs() {
+            for entry in lua.named_registry_value::<Table>(handle).or(lua.create_table())?.pairs() {
                 let (key, value) = entry?;
                 res.set::<Value, Value>(key, value)?;
             }
@@ -123,7 +222,8 @@ fn root_keys<'lua>(lua: &'lua Lua, key_array: rlua::Value<'lua>) -> rlua::Result
         }
         v => {
             Err(rlua::Error::RuntimeError(format!("Expected nil or array \
-                                                   of keys, got {:?}",
+                                                   of {}, got {:?}",
+                                                  kind,
                                                   v)))
         }
     }
@@ -134,6 +234,7 @@ mod test {
     use super::super::root;
     use super::super::tag;
     use super::super::key;
+    use super::super::button;
     use rlua::Lua;
 
     #[test]
@@ -230,4 +331,43 @@ assert(res[3] == nil)
                  None
         ).unwrap()
     }
+
+    #[test]
+    fn buttons() {
+        let lua = Lua::new();
+        button::init(&lua).unwrap();
+        root::init(&lua).unwrap();
+        lua.eval(
+                 r#"
+assert(next(root.buttons()) == nil)
+
+local first = button{}
+local second = button{}
+local buttons = { first, second }
+
+local res = root.buttons(buttons)
+assert(res[1] == first)
+assert(res[2] == second)
+assert(res[3] == nil)
+
+buttons[3] = button{}
+local res = root.buttons()
+assert(res[1] == first)
+assert(res[2] == second)
+assert(res[3] == nil)
+"#,
+                 None
+        ).unwrap()
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(root::parse_color("#ff0000"), Some([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(root::parse_color("#00FF0000"), Some([0.0, 1.0, 0.0, 0.0]));
+        assert_eq!(root::parse_color("#0000ff80"), Some([0.0, 0.0, 1.0, 128.0 / 255.0]));
+        assert_eq!(root::parse_color("ff0000"), None);
+        assert_eq!(root::parse_color("#ff00"), None);
+        assert_eq!(root::parse_color("#ff00zz"), None);
+        assert_eq!(root::parse_color("#ff0000ff00"), None);
+    }
 }
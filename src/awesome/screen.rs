@@ -1,13 +1,15 @@
 //! TODO Fill in
 
-use awesome::{class::{self, Class, ClassBuilder}, object::{self, Object, Objectable},
-              property::Property};
-use rlua::{self, AnyUserData, Lua, MetaMethod, Table, ToLua, UserData, UserDataMethods, Value};
+use awesome::{capture, class::{self, Class, ClassBuilder}, drawin,
+              object::{self, Object, Objectable}, output, property::Property, signal};
+use rlua::{self, AnyUserData, FromLua, Function, Lua, MetaMethod, Table, ToLua, UserData,
+           UserDataMethods, Value};
 use std::default::Default;
 use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
 use wlroots::{Area, Origin, OutputHandle, Size};
 
-use compositor::Server;
+use compositor::{self, GammaRamps, OutputConfig, Server, NEUTRAL_TEMPERATURE};
 
 pub const SCREENS_HANDLE: &'static str = "__screens";
 
@@ -24,6 +26,8 @@ pub struct ScreenState {
     pub workarea: Area,
     // The screen outputs information
     pub outputs: Vec<OutputHandle>,
+    // How much bigger things are drawn on the outputs, e.g 2 on HiDPI panels
+    pub scale: f32,
     // Some XID indetifying this screen
     pub xid: u32
 }
@@ -50,6 +54,7 @@ impl Default for ScreenState {
                       geometry: Area::default(),
                       workarea: Area::default(),
                       outputs: vec![],
+                      scale: 1.0,
                       xid: 0 }
     }
 }
@@ -68,19 +73,26 @@ impl<'lua> Screen<'lua> {
     }
 
     fn init_screens(&mut self,
-                    output: OutputHandle,
-                    outputs: Vec<OutputHandle>)
+                    outputs: Vec<OutputHandle>,
+                    geometry: Area,
+                    workarea: Area)
                     -> rlua::Result<()> {
         let mut state = self.get_object_mut()?;
-        let (width, height) = output.run(|output| output.effective_resolution())
-                                    .expect("Output handle was invalid");
-        let resolution = Size { width, height };
+        state.scale = outputs_scale(&outputs);
         state.outputs = outputs;
-        state.geometry = state.geometry.with_size(resolution);
-        state.workarea = state.workarea.with_size(resolution);
+        state.geometry = geometry;
+        state.workarea = workarea;
         Ok(())
     }
 
+    /// Makes a screen that isn't showing any outputs, for when there are none.
+    fn fake(lua: &'lua Lua) -> rlua::Result<Object<'lua>> {
+        let obj = Screen::new(lua)?;
+        let geometry = Size::new(1024, 768).into();
+        Screen::cast(obj.clone())?.init_screens(vec![], geometry, geometry)?;
+        Ok(obj)
+    }
+
     fn get_geometry(&self, lua: &'lua Lua) -> rlua::Result<Table<'lua>> {
         let state = self.state()?;
         let Origin { x, y } = state.geometry.origin;
@@ -107,41 +119,213 @@ impl<'lua> Screen<'lua> {
 }
 
 pub fn init<'lua>(lua: &'lua Lua, server: &mut Server) -> rlua::Result<Class<'lua>> {
-    let builder = Class::builder(lua, "screen", None)?;
-    let res = property_setup(lua, method_setup(lua, builder)?)?.save_class("screen")?
-                                                               .build()?;
+    let res = build_class(lua)?;
     let screens: &mut Vec<Screen> = &mut vec![];
-    for output in server.outputs.iter() {
+    let Server { ref mut layout,
+                 ref outputs,
+                 ref layer_surfaces,
+                 ref idle,
+                 .. } = *server;
+    for (output, geometry) in compositor::output_boxes(layout, outputs, idle.powered_off()) {
+        let workarea = compositor::arrange_layers(layout, &output, layer_surfaces);
         let mut screen = Screen::cast(Screen::new(lua)?)?;
-        screen.init_screens(output.clone(), vec![output.clone()])?;
-        // TODO Move to Screen impl like the others
+        screen.init_screens(vec![output], geometry, workarea)?;
         screens.push(screen);
     }
 
     // If no screens exist, fake one.
     if screens.is_empty() {
-        let mut screen = Screen::cast(Screen::new(lua)?)?;
-        {
-            let mut obj = screen.get_object_mut()?;
-            obj.geometry = Size::new(1024, 768).into();
-            obj.workarea = obj.geometry;
-        }
-        screens.push(screen);
+        screens.push(Screen::cast(Screen::fake(lua)?)?);
     }
 
     lua.set_named_registry_value(SCREENS_HANDLE, screens.clone().to_lua(lua)?)?;
     Ok(res)
 }
 
+fn build_class(lua: &Lua) -> rlua::Result<Class> {
+    let builder = Class::builder(lua, "screen", Some(Arc::new(is_valid)))?;
+    property_setup(lua, method_setup(lua, builder)?)?.save_class("screen")?
+                                                     .build()
+}
+
+/// Whether the screen is still around, i.e `screen.valid`.
+fn is_valid(obj: Object) -> bool {
+    Screen::cast(obj).and_then(|screen| Ok(screen.state()?.valid))
+                     .unwrap_or(false)
+}
+
+/// Brings the screens in line with the outputs, e.g because one was plugged
+/// in or unplugged.
+///
+/// `outputs` are all of the outputs, along with where they are in the layout
+/// and their workarea.
+pub fn update_screens(lua: &Lua, outputs: &[(OutputHandle, Area, Area)]) -> rlua::Result<()> {
+    let old_screens = match lua.named_registry_value::<Value>(SCREENS_HANDLE)? {
+        Value::Nil => return Ok(()),
+        screens => Vec::<AnyUserData>::from_lua(screens, lua)?
+    };
+    let old_primary = old_screens.first().cloned();
+    let mut screens = Vec::new();
+    let mut known_outputs = Vec::new();
+    let (mut added, mut removed) = (Vec::new(), Vec::new());
+    let (mut moved, mut resized_workarea, mut rescaled) = (Vec::new(), Vec::new(), Vec::new());
+    for obj in old_screens {
+        let mut screen = Screen::cast(obj.clone().into())?;
+        let mut state = screen.get_object_mut()?;
+        let areas = outputs.iter()
+                           .find(|&&(ref output, ..)| state.outputs.contains(output))
+                           .map(|&(_, geometry, workarea)| (geometry, workarea));
+        match areas {
+            Some((geometry, workarea)) => {
+                if state.geometry != geometry {
+                    state.geometry = geometry;
+                    moved.push(obj.clone());
+                }
+                if state.workarea != workarea {
+                    state.workarea = workarea;
+                    resized_workarea.push(obj.clone());
+                }
+                let scale = outputs_scale(&state.outputs);
+                if state.scale != scale {
+                    state.scale = scale;
+                    rescaled.push(obj.clone());
+                }
+                known_outputs.extend(state.outputs.iter().cloned());
+                screens.push(obj.clone());
+            }
+            // The fake screen stays until there is a real one.
+            None if state.outputs.is_empty() && outputs.is_empty() => screens.push(obj.clone()),
+            None => {
+                state.valid = false;
+                removed.push(obj.clone());
+            }
+        }
+    }
+    for &(ref output, geometry, workarea) in outputs {
+        if known_outputs.contains(output) {
+            continue
+        }
+        let obj = Screen::new(lua)?;
+        Screen::cast(obj.clone())?.init_screens(vec![output.clone()], geometry, workarea)?;
+        screens.push(obj.object.clone());
+        added.push(obj.object);
+    }
+    if screens.is_empty() {
+        let obj = Screen::fake(lua)?;
+        screens.push(obj.object.clone());
+        added.push(obj.object);
+    }
+    lua.set_named_registry_value(SCREENS_HANDLE, screens.clone().to_lua(lua)?)?;
+
+    let class = class::class_setup(lua, "screen")?;
+    for obj in removed {
+        signal::emit_class_signal(lua, class.clone(), "removed".into(), obj)?;
+    }
+    for obj in added {
+        signal::emit_class_signal(lua, class.clone(), "added".into(), obj)?;
+    }
+    for obj in moved {
+        signal::emit_object_signal(lua, obj.into(), "property::geometry".into(), ())?;
+    }
+    for obj in resized_workarea {
+        signal::emit_object_signal(lua, obj.into(), "property::workarea".into(), ())?;
+    }
+    for obj in rescaled {
+        signal::emit_object_signal(lua, obj.into(), "property::scale".into(), ())?;
+    }
+    // Drawins might now be on a screen with a different scale.
+    drawin::update_scales(lua)?;
+    let new_primary = screens.first().cloned();
+    let primary_changed = match (&old_primary, &new_primary) {
+        (&Some(ref old), &Some(ref new)) => !same_screen(old, new)?,
+        _ => true
+    };
+    if primary_changed {
+        for obj in old_primary.into_iter().chain(new_primary) {
+            signal::emit_object_signal(lua, obj.into(), "primary_changed".into(), ())?;
+        }
+    }
+    Ok(())
+}
+
+/// The scale of the screen showing the outputs.
+///
+/// If the outputs have different scales the biggest one is used, so that
+/// nothing is blurry.
+fn outputs_scale(outputs: &[OutputHandle]) -> f32 {
+    let scales = outputs.iter()
+                        .filter_map(|output| output.run(|output| output.scale()).ok());
+    scales.fold(None, |biggest, scale| match biggest {
+                    Some(biggest) if biggest >= scale => Some(biggest),
+                    _ => Some(scale)
+                })
+          .unwrap_or(1.0)
+}
+
+/// The scale of the screen at the point, in output layout coordinates.
+///
+/// If no screen is there, the scale of the primary screen is used.
+pub fn scale_at(lua: &Lua, point: Origin) -> rlua::Result<f32> {
+    let screens = match lua.named_registry_value::<Value>(SCREENS_HANDLE)? {
+        Value::Nil => return Ok(1.0),
+        screens => Vec::<AnyUserData>::from_lua(screens, lua)?
+    };
+    let mut primary_scale = None;
+    for obj in screens {
+        let screen = Screen::cast(obj.into())?;
+        let state = screen.state()?;
+        let Area { origin, size } = state.geometry;
+        if point.x >= origin.x && point.x < origin.x + size.width && point.y >= origin.y
+           && point.y < origin.y + size.height
+        {
+            return Ok(state.scale)
+        }
+        primary_scale = primary_scale.or(Some(state.scale));
+    }
+    Ok(primary_scale.unwrap_or(1.0))
+}
+
+/// Whether both of the objects are the same screen.
+fn same_screen(a: &AnyUserData, b: &AnyUserData) -> rlua::Result<bool> {
+    let a = &*a.borrow::<ScreenState>()? as *const ScreenState;
+    let b = &*b.borrow::<ScreenState>()? as *const ScreenState;
+    Ok(a == b)
+}
+
+/// Updates the workarea of the screen showing the output, e.g because a
+/// panel reserved some space on it.
+pub fn update_workarea(lua: &Lua, output: &OutputHandle, workarea: Area) -> rlua::Result<()> {
+    let screens = match lua.named_registry_value::<Value>(SCREENS_HANDLE)? {
+        Value::Nil => return Ok(()),
+        screens => Vec::<AnyUserData>::from_lua(screens, lua)?
+    };
+    for obj in screens {
+        let mut screen = Screen::cast(obj.clone().into())?;
+        {
+            let mut state = screen.get_object_mut()?;
+            if !state.outputs.contains(output) || state.workarea == workarea {
+                continue
+            }
+            state.workarea = workarea;
+        }
+        signal::emit_object_signal(lua, obj.into(), "property::workarea".into(), ())?;
+    }
+    Ok(())
+}
+
 fn method_setup<'lua>(lua: &'lua Lua,
                       builder: ClassBuilder<'lua>)
                       -> rlua::Result<ClassBuilder<'lua>> {
-    // TODO Do properly
-    use super::dummy;
-    builder.method("connect_signal".into(), lua.create_function(dummy)?)?
-           .method("count".into(), lua.create_function(count)?)?
+    builder.method("count".into(), lua.create_function(count)?)?
            .method("__call".into(), lua.create_function(iterate_over_screens)?)?
-           .method("__index".into(), lua.create_function(index)?)
+           .method("__index".into(), lua.create_function(index)?)?
+           .method("set_mode".into(), lua.create_function(set_mode)?)?
+           .method("set_scale".into(), lua.create_function(set_scale)?)?
+           .method("set_transform".into(), lua.create_function(set_transform)?)?
+           .method("set_position".into(), lua.create_function(set_position)?)?
+           .method("content".into(), lua.create_function(content)?)?
+           .method("set_gamma".into(), lua.create_function(set_gamma)?)?
+           .method("set_temperature".into(), lua.create_function(set_temperature)?)
 }
 
 fn property_setup<'lua>(lua: &'lua Lua,
@@ -154,6 +338,10 @@ fn property_setup<'lua>(lua: &'lua Lua,
            .property(Property::new("workarea".into(),
                                    None,
                                    Some(lua.create_function(get_workarea)?),
+                                   None))?
+           .property(Property::new("scale".into(),
+                                   None,
+                                   Some(lua.create_function(get_scale)?),
                                    None))
 }
 
@@ -167,6 +355,128 @@ fn get_workarea<'lua>(lua: &'lua Lua, object: AnyUserData<'lua>) -> rlua::Result
     screen.get_workarea(lua)
 }
 
+/// Changes the configuration of every output shown on the screen.
+fn configure_screen(lua: &Lua, object: AnyUserData, config: OutputConfig) -> rlua::Result<()> {
+    for name in output_names(object)? {
+        output::configure(lua, &name, config.clone(), false)?;
+    }
+    Ok(())
+}
+
+fn set_mode<'lua>(lua: &'lua Lua,
+                  (object, mode): (AnyUserData<'lua>, Table<'lua>))
+                  -> rlua::Result<()> {
+    let config = OutputConfig { mode: Some(output::mode_from_table(mode)?),
+                                ..OutputConfig::default() };
+    configure_screen(lua, object, config)
+}
+
+fn set_scale<'lua>(lua: &'lua Lua, (object, scale): (AnyUserData<'lua>, f32)) -> rlua::Result<()> {
+    let config = OutputConfig { scale: Some(scale),
+                                ..OutputConfig::default() };
+    configure_screen(lua, object, config)
+}
+
+fn set_transform<'lua>(lua: &'lua Lua,
+                       (object, transform): (AnyUserData<'lua>, Value<'lua>))
+                       -> rlua::Result<()> {
+    let config = OutputConfig { transform: Some(output::transform_from_value(transform)?),
+                                ..OutputConfig::default() };
+    configure_screen(lua, object, config)
+}
+
+fn set_position<'lua>(lua: &'lua Lua,
+                      (object, position): (AnyUserData<'lua>, Table<'lua>))
+                      -> rlua::Result<()> {
+    let config = OutputConfig { position: Some(output::position_from_table(position)?),
+                                ..OutputConfig::default() };
+    configure_screen(lua, object, config)
+}
+
+/// The names of the outputs shown on the screen.
+fn output_names(object: AnyUserData) -> rlua::Result<Vec<String>> {
+    let screen = Screen::cast(object.into())?;
+    let outputs = screen.state()?.outputs.clone();
+    Ok(outputs.iter()
+              .filter_map(|output| output.run(|output| output.name()).ok())
+              .collect())
+}
+
+/// Sets the gamma ramps of the screen, as tables of `red`, `green` and
+/// `blue` values from 0 to 1.
+///
+/// If `nil` the default ramps are restored.
+fn set_gamma<'lua>(_: &'lua Lua,
+                   (object, ramps): (AnyUserData<'lua>, Option<Table<'lua>>))
+                   -> rlua::Result<()> {
+    let ramps = match ramps {
+        Some(ramps) => {
+            let channel = |name: &str| -> rlua::Result<Vec<u16>> {
+                let values = ramps.get::<_, Vec<f64>>(name)?;
+                Ok(values.into_iter()
+                         .map(|value| (value.max(0.0).min(1.0) * 65535.0) as u16)
+                         .collect())
+            };
+            Some(GammaRamps { red: channel("red")?,
+                              green: channel("green")?,
+                              blue: channel("blue")? })
+        }
+        None => None
+    };
+    for name in output_names(object)? {
+        if let Some(ref ramps) = ramps {
+            let size = compositor::gamma_size(&name).unwrap_or(0);
+            ramps.validate(size)
+                 .map_err(|err| rlua::Error::RuntimeError(format!("{}: {}", name, err)))?;
+        }
+        compositor::set_gamma(&name, ramps.clone());
+    }
+    Ok(())
+}
+
+/// Tints the screen to a colour temperature in Kelvin (e.g 3500 at night),
+/// optionally dimming it with a brightness from 0 to 1.
+fn set_temperature<'lua>(_: &'lua Lua,
+                         (object, temperature, brightness): (AnyUserData<'lua>,
+                                                             u32,
+                                                             Option<f64>))
+                         -> rlua::Result<()> {
+    let brightness = brightness.unwrap_or(1.0).max(0.0).min(1.0);
+    for name in output_names(object)? {
+        let size = match compositor::gamma_size(&name) {
+            Some(size) if size > 0 => size,
+            _ => continue
+        };
+        // Daylight at full brightness is the same as no ramps at all.
+        let ramps = if temperature == NEUTRAL_TEMPERATURE && brightness == 1.0 {
+            None
+        } else {
+            Some(GammaRamps::from_temperature(temperature, brightness, size))
+        };
+        compositor::set_gamma(&name, ramps);
+    }
+    Ok(())
+}
+
+/// Calls `callback` with what is shown on the screen, as a cairo surface at
+/// the scale of the screen, once it has drawn its next frame.
+fn content<'lua>(lua: &'lua Lua,
+                 (object, callback): (AnyUserData<'lua>, Function<'lua>))
+                 -> rlua::Result<()> {
+    let screen = Screen::cast(object.into())?;
+    let (geometry, scale) = {
+        let state = screen.state()?;
+        (state.geometry, state.scale)
+    };
+    capture::capture_area(lua, geometry, scale, callback)
+}
+
+fn get_scale<'lua>(_: &'lua Lua, object: AnyUserData<'lua>) -> rlua::Result<f32> {
+    let screen = Screen::cast(object.into())?;
+    let scale = screen.state()?.scale;
+    Ok(scale)
+}
+
 fn count<'lua>(lua: &'lua Lua, _: ()) -> rlua::Result<Value<'lua>> {
     let screens = lua.named_registry_value::<Vec<AnyUserData>>(SCREENS_HANDLE)?;
     Ok(Value::Integer(screens.len() as _))
@@ -262,3 +572,35 @@ fn index<'lua>(lua: &'lua Lua,
         Ok(value) => Ok(value)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::drawin;
+    use super::super::screen::{self, Screen, SCREENS_HANDLE};
+    use rlua::{AnyUserData, Lua, ToLua};
+    use wlroots::{OutputHandle, Size};
+
+    #[test]
+    fn removed_screen_is_invalid() {
+        let lua = Lua::new();
+        drawin::init(&lua).unwrap();
+        screen::build_class(&lua).unwrap();
+        let obj = Screen::new(&lua).unwrap();
+        let geometry = Size::new(1920, 1080).into();
+        Screen::cast(obj.clone()).unwrap()
+                                 .init_screens(vec![OutputHandle::default()], geometry, geometry)
+                                 .unwrap();
+        lua.set_named_registry_value(SCREENS_HANDLE, vec![obj.clone()].to_lua(&lua).unwrap())
+           .unwrap();
+        lua.globals().set("s", obj).unwrap();
+        screen::update_screens(&lua, &[]).unwrap();
+        let screens: Vec<AnyUserData> = lua.named_registry_value(SCREENS_HANDLE).unwrap();
+        lua.globals().set("fake", screens[0].clone()).unwrap();
+        lua.eval::<()>(r#"
+assert(s.valid == false)
+assert(fake.valid == true)
+"#,
+                       None)
+           .unwrap()
+    }
+}
@@ -0,0 +1,23 @@
+//! The `selection()` function, which returns the text of the primary
+//! selection (i.e what is pasted with a middle click).
+//!
+//! `selection::changed` is emitted with "clipboard" or "primary" when one of
+//! the selections of the seat changes.
+
+use rlua::{self, Lua};
+
+use compositor;
+
+/// Init the methods defined on this interface.
+pub fn init(lua: &Lua) -> rlua::Result<()> {
+    let globals = lua.globals();
+    globals.set("selection", lua.create_function(selection)?)
+}
+
+/// The text of the primary selection, or nil if there is none.
+///
+/// The selection is read in the background when it changes, so right after
+/// that this is nil for a moment.
+fn selection<'lua>(_: &'lua Lua, _: ()) -> rlua::Result<Option<String>> {
+    Ok(compositor::primary_selection())
+}
@@ -4,6 +4,7 @@
 //! Signals are stored with the object in its metatable,
 //! the methods defined here are just to make it easier to use.
 
+use super::class::Class;
 use super::{Object, GLOBAL_SIGNALS};
 use rlua::{self, Function, Lua, Table, ToLua, ToLuaMulti, Value};
 
@@ -62,6 +63,35 @@ pub fn emit_object_signal<'lua, A>(lua: &'lua Lua,
     emit_signals(lua, signals, name, args)
 }
 
+/// Connects functions to a signal of a class, e.g `screen.connect_signal`.
+pub fn connect_class_signal(lua: &Lua,
+                            class: Class,
+                            name: String,
+                            funcs: &[Function])
+                            -> rlua::Result<()> {
+    let signals = class.signals()?;
+    connect_signals(lua, signals, name, funcs)
+}
+
+pub fn disconnect_class_signal(lua: &Lua, class: Class, name: String) -> rlua::Result<()> {
+    let signals = class.signals()?;
+    disconnect_signals(lua, signals, name)
+}
+
+/// Evaluate the functions associated with a signal of a class.
+///
+/// Unlike with object signals, nothing is passed besides the arguments.
+pub fn emit_class_signal<'lua, A>(lua: &'lua Lua,
+                                  class: Class<'lua>,
+                                  name: String,
+                                  args: A)
+                                  -> rlua::Result<()>
+    where A: ToLuaMulti<'lua> + Clone
+{
+    let signals = class.signals()?;
+    emit_signals(lua, signals, name, args)
+}
+
 fn emit_signals<'lua, A>(_: &'lua Lua,
                          signals: Table<'lua>,
                          name: String,
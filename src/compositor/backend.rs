@@ -0,0 +1,140 @@
+//! Selects which wlroots backend Way Cooler runs on.
+//!
+//! wlroots picks its backends from the environment when the compositor is
+//! built, so selecting one is a matter of exporting the right variables
+//! before `build_auto` is called.
+
+use std::env;
+use wlroots::Size;
+
+/// Size of a headless output when none was requested.
+pub const DEFAULT_HEADLESS_SIZE: Size = Size { width: 1280,
+                                               height: 720 };
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Backend {
+    /// Let wlroots decide based on the environment it was started in.
+    Auto,
+    /// Run directly on the hardware, using a DRM device and libinput.
+    Drm,
+    /// Run nested in a Wayland compositor, with that many windows as outputs.
+    Wayland { outputs: usize },
+    /// Run nested in an X11 server, with that many windows as outputs.
+    X11 { outputs: usize },
+    /// Run without any GPU or seat, rendering into virtual outputs.
+    Headless { outputs: usize, size: Size }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Auto
+    }
+}
+
+impl Backend {
+    /// Parses a backend from its name, e.g "headless" or "x11".
+    ///
+    /// `outputs` and `size` are only used by the backends that create
+    /// virtual outputs, the others ignore them.
+    pub fn from_name(name: &str,
+                     outputs: Option<usize>,
+                     size: Option<Size>)
+                     -> Result<Self, String> {
+        let outputs = outputs.unwrap_or(1);
+        if outputs == 0 {
+            return Err("At least one output is required".into())
+        }
+        match name {
+            "auto" => Ok(Backend::Auto),
+            "drm" => Ok(Backend::Drm),
+            "wayland" => Ok(Backend::Wayland { outputs }),
+            "x11" => Ok(Backend::X11 { outputs }),
+            "headless" => Ok(Backend::Headless { outputs,
+                                                 size: size.unwrap_or(DEFAULT_HEADLESS_SIZE) }),
+            name => Err(format!("Unknown backend \"{}\", expected one of auto, drm, wayland, \
+                                 x11 or headless",
+                                name))
+        }
+    }
+
+    /// Exports the environment variables wlroots uses to pick its backends.
+    pub fn export(&self) {
+        match *self {
+            Backend::Auto => {}
+            Backend::Drm => env::set_var("WLR_BACKENDS", "drm,libinput"),
+            Backend::Wayland { outputs } => {
+                env::set_var("WLR_BACKENDS", "wayland");
+                env::set_var("WLR_WL_OUTPUTS", outputs.to_string());
+            }
+            Backend::X11 { outputs } => {
+                env::set_var("WLR_BACKENDS", "x11");
+                env::set_var("WLR_X11_OUTPUTS", outputs.to_string());
+            }
+            Backend::Headless { outputs, .. } => {
+                env::set_var("WLR_BACKENDS", "headless");
+                env::set_var("WLR_HEADLESS_OUTPUTS", outputs.to_string());
+            }
+        }
+    }
+
+    /// The size all outputs should be forced to, if any.
+    pub fn output_size(&self) -> Option<Size> {
+        match *self {
+            Backend::Headless { size, .. } => Some(size),
+            _ => None
+        }
+    }
+}
+
+/// Parses a size of the form "WIDTHxHEIGHT", e.g "1920x1080".
+pub fn parse_size(size: &str) -> Result<Size, String> {
+    let invalid = || format!("Invalid size \"{}\", expected WIDTHxHEIGHT", size);
+    let mut split = size.splitn(2, 'x');
+    let width = split.next()
+                     .and_then(|width| width.trim().parse::<i32>().ok())
+                     .ok_or_else(invalid)?;
+    let height = split.next()
+                      .and_then(|height| height.trim().parse::<i32>().ok())
+                      .ok_or_else(invalid)?;
+    if width <= 0 || height <= 0 {
+        return Err(invalid())
+    }
+    Ok(Size { width, height })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("1920x1080"), Ok(Size { width: 1920,
+                                                      height: 1080 }));
+        assert_eq!(parse_size(" 800 x 600 "), Ok(Size { width: 800,
+                                                        height: 600 }));
+        assert!(parse_size("1920").is_err());
+        assert!(parse_size("1920x").is_err());
+        assert!(parse_size("0x1080").is_err());
+        assert!(parse_size("-1x1080").is_err());
+        assert!(parse_size("widexhigh").is_err());
+    }
+
+    #[test]
+    fn backend_names() {
+        assert_eq!(Backend::from_name("auto", None, None), Ok(Backend::Auto));
+        assert_eq!(Backend::from_name("drm", Some(2), None), Ok(Backend::Drm));
+        assert_eq!(Backend::from_name("x11", Some(2), None),
+                   Ok(Backend::X11 { outputs: 2 }));
+        assert_eq!(Backend::from_name("wayland", None, None),
+                   Ok(Backend::Wayland { outputs: 1 }));
+        assert_eq!(Backend::from_name("headless", None, None),
+                   Ok(Backend::Headless { outputs: 1,
+                                          size: DEFAULT_HEADLESS_SIZE }));
+        let size = Size { width: 640,
+                          height: 480 };
+        assert_eq!(Backend::from_name("headless", Some(3), Some(size)),
+                   Ok(Backend::Headless { outputs: 3, size }));
+        assert!(Backend::from_name("headless", Some(0), None).is_err());
+        assert!(Backend::from_name("fbdev", None, None).is_err());
+    }
+}
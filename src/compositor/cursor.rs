@@ -1,4 +1,14 @@
-use wlroots::CursorHandler;
+use std::env;
+
+use wlroots::{self, CursorHandler, XCursorManager};
+
+use compositor::Server;
+
+/// The cursor theme used if `XCURSOR_THEME` isn't set.
+const DEFAULT_THEME: &str = "default";
+/// The cursor size used if `XCURSOR_SIZE` isn't set.
+const DEFAULT_SIZE: u32 = 24;
+
 #[derive(Debug, Default)]
 pub struct CursorManager;
 
@@ -9,3 +19,106 @@ impl CursorManager {
         CursorManager::default()
     }
 }
+
+/// The xcursor theme, shared with the clients we start.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CursorTheme {
+    pub name: String,
+    pub size: u32
+}
+
+impl Default for CursorTheme {
+    fn default() -> Self {
+        CursorTheme { name: DEFAULT_THEME.into(),
+                      size: DEFAULT_SIZE }
+    }
+}
+
+impl CursorTheme {
+    /// The theme set in `XCURSOR_THEME` and `XCURSOR_SIZE`.
+    pub fn from_env() -> Self {
+        let default = CursorTheme::default();
+        let name = env::var("XCURSOR_THEME").ok()
+                                            .filter(|name| !name.is_empty())
+                                            .unwrap_or(default.name);
+        let size = env::var("XCURSOR_SIZE").ok()
+                                           .and_then(|size| size.parse().ok())
+                                           .filter(|&size| size > 0)
+                                           .unwrap_or(default.size);
+        CursorTheme { name, size }
+    }
+
+    /// Tells the clients we start to use the theme.
+    pub fn export(&self) {
+        env::set_var("XCURSOR_THEME", &self.name);
+        env::set_var("XCURSOR_SIZE", self.size.to_string());
+    }
+
+    pub fn create_manager(&self) -> Option<XCursorManager> {
+        XCursorManager::create(self.name.clone(), self.size)
+    }
+}
+
+/// Loads the cursor theme at the scale of every output, so that it is sharp
+/// on all of them, and shows the default cursor if no client set one.
+pub fn load_cursor_theme(server: &mut Server) {
+    let Server { ref outputs,
+                 ref seat,
+                 ref mut cursor,
+                 ref mut xcursor_manager,
+                 .. } = *server;
+    xcursor_manager.load(1.0);
+    for output in outputs {
+        output.run(|output| xcursor_manager.load(output.scale())).ok();
+    }
+    if !seat.has_client_cursor {
+        with_handles!([(cursor: {&mut *cursor})] => {
+            xcursor_manager.set_cursor_image(seat.default_cursor(), cursor)
+        }).unwrap();
+    }
+}
+
+/// Changes the cursor theme from outside of a compositor callback, e.g
+/// from Lua.
+pub fn set_cursor_theme(theme: CursorTheme) -> Result<(), String> {
+    let xcursor_manager = theme.create_manager()
+                               .ok_or_else(|| format!("Could not load cursor theme {:?}", theme))?;
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        theme.export();
+        server.xcursor_manager = xcursor_manager;
+        server.cursor_theme = theme;
+        load_cursor_theme(server);
+        Ok(())
+    )
+}
+
+/// Sets the cursor shown when the pointer is over no client, from outside of
+/// a compositor callback (e.g from Lua).
+pub fn set_default_cursor(name: String) {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref mut seat,
+                     ref mut cursor,
+                     ref mut xcursor_manager,
+                     .. } = *server;
+        seat.default_cursor = Some(name);
+        if !seat.has_client_cursor {
+            with_handles!([(cursor: {&mut *cursor})] => {
+                xcursor_manager.set_cursor_image(seat.default_cursor(), cursor)
+            }).unwrap();
+        }
+    )
+}
+
+/// The current cursor theme, from outside of a compositor callback (e.g from
+/// Lua).
+pub fn cursor_theme() -> CursorTheme {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.cursor_theme.clone()
+    )
+}
@@ -0,0 +1,259 @@
+//! Keeps track of which parts of the outputs need to be repainted, so that
+//! outputs are only rendered when something on them changed.
+
+use std::cmp::{max, min};
+use std::mem;
+
+use wlroots::{self, Area, Origin, OutputHandle, Size};
+
+use compositor::Server;
+
+/// How far around the pointer is damaged when it moves.
+///
+/// This is big enough to cover any sensible cursor image, wherever its
+/// hotspot is.
+const CURSOR_DAMAGE_RADIUS: i32 = 64;
+
+/// The damage accumulated on an output since it was last rendered.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+struct OutputDamage {
+    output: OutputHandle,
+    /// The bounding box of everything that was damaged, in output layout
+    /// coordinates.
+    current: Area,
+    /// What changed in the previous frame.
+    ///
+    /// If the buffer we are about to draw into was last drawn two frames
+    /// ago, it is missing whatever changed in between.
+    previous: Area,
+    /// Everything on the output must be repainted, e.g because it was just
+    /// added to the layout.
+    whole: bool
+}
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Damage {
+    outputs: Vec<OutputDamage>
+}
+
+impl Damage {
+    /// Starts tracking the damage of an output, which is completely damaged.
+    pub fn add_output(&mut self, output: OutputHandle) {
+        schedule_frame(&output);
+        self.outputs.push(OutputDamage { output,
+                                         whole: true,
+                                         ..OutputDamage::default() });
+    }
+
+    pub fn remove_output(&mut self, output: &OutputHandle) {
+        self.outputs.retain(|damage| damage.output != *output);
+    }
+
+    /// Damages an area, in output layout coordinates.
+    pub fn add_area(&mut self, area: Area) {
+        if area.size.width <= 0 || area.size.height <= 0 {
+            return
+        }
+        for damage in &mut self.outputs {
+            damage.current = union(damage.current, area);
+            schedule_frame(&damage.output);
+        }
+    }
+
+    /// Damages the area under the pointer at the given layout coordinates.
+    pub fn add_cursor(&mut self, lx: f64, ly: f64) {
+        let origin = Origin::new(lx as i32 - CURSOR_DAMAGE_RADIUS,
+                                 ly as i32 - CURSOR_DAMAGE_RADIUS);
+        let size = Size::new(CURSOR_DAMAGE_RADIUS * 2, CURSOR_DAMAGE_RADIUS * 2);
+        self.add_area(Area::new(origin, size))
+    }
+
+    /// Damages everything on every output.
+    pub fn add_whole(&mut self) {
+        for damage in &mut self.outputs {
+            damage.whole = true;
+            schedule_frame(&damage.output);
+        }
+    }
+
+    /// Damages everything on one output, e.g because a client wants a copy
+    /// of it.
+    pub fn add_whole_output(&mut self, output: &OutputHandle) {
+        for damage in self.outputs.iter_mut().filter(|damage| damage.output == *output) {
+            damage.whole = true;
+            schedule_frame(&damage.output);
+        }
+    }
+
+    /// Takes the damage of an output in order to repaint it.
+    ///
+    /// `output_box` is where the output is in the layout. The returned area
+    /// is clipped to it. If nothing on the output changed `None` is returned,
+    /// and the output does not need to be rendered at all.
+    ///
+    /// `buffer_age` is how many frames ago the buffer that is drawn into was
+    /// last drawn, if that is known. The whole output is repainted if it
+    /// isn't, or if it is older than the damage that is kept around.
+    pub fn take(&mut self,
+                output: &OutputHandle,
+                output_box: Area,
+                buffer_age: Option<i32>)
+                -> Option<Area> {
+        let damage = match self.outputs.iter_mut().find(|damage| damage.output == *output) {
+            Some(damage) => damage,
+            None => return Some(output_box)
+        };
+        let current = mem::replace(&mut damage.current, Area::default());
+        let new = if damage.whole {
+            Some(output_box)
+        } else {
+            intersection(current, output_box)
+        };
+        damage.whole = false;
+        // If nothing is drawn the buffers are not swapped either, so the
+        // previous damage still applies to the next frame.
+        let new = new?;
+        let previous = mem::replace(&mut damage.previous, new);
+        match buffer_age {
+            // The buffer has the previous frame in it.
+            Some(1) => Some(new),
+            Some(2) => Some(union(previous, new)),
+            // The buffer is new, or too old to know what it is missing.
+            _ => Some(output_box)
+        }
+    }
+}
+
+/// Damages an area, in output layout coordinates, from outside of a
+/// compositor callback, e.g from Lua.
+pub fn damage_area(area: Area) {
+    let compositor = match wlroots::compositor_handle() {
+        Some(compositor) => compositor,
+        None => return
+    };
+    let res = compositor.run(|compositor| {
+                                 let server: &mut Server = compositor.into();
+                                 server.damage.add_area(area)
+                             });
+    if let Err(err) = res {
+        warn!("Could not damage {:?}: {:?}", area, err);
+    }
+}
+
+/// The area covered by both of the areas, or `None` if they don't overlap.
+pub fn intersection(a: Area, b: Area) -> Option<Area> {
+    let x1 = max(a.origin.x, b.origin.x);
+    let y1 = max(a.origin.y, b.origin.y);
+    let x2 = min(a.origin.x + a.size.width, b.origin.x + b.size.width);
+    let y2 = min(a.origin.y + a.size.height, b.origin.y + b.size.height);
+    if x2 <= x1 || y2 <= y1 {
+        return None
+    }
+    Some(Area::new(Origin::new(x1, y1), Size::new(x2 - x1, y2 - y1)))
+}
+
+/// The smallest area containing both of the areas.
+///
+/// Empty areas are ignored.
+pub fn union(a: Area, b: Area) -> Area {
+    if a.size.width <= 0 || a.size.height <= 0 {
+        return b
+    }
+    if b.size.width <= 0 || b.size.height <= 0 {
+        return a
+    }
+    let x1 = min(a.origin.x, b.origin.x);
+    let y1 = min(a.origin.y, b.origin.y);
+    let x2 = max(a.origin.x + a.size.width, b.origin.x + b.size.width);
+    let y2 = max(a.origin.y + a.size.height, b.origin.y + b.size.height);
+    Area::new(Origin::new(x1, y1), Size::new(x2 - x1, y2 - y1))
+}
+
+fn schedule_frame(output: &OutputHandle) {
+    // This only fails if the output is being rendered right now, and nothing
+    // is damaged while rendering.
+    output.run(|output| output.schedule_frame()).ok();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn area(x: i32, y: i32, width: i32, height: i32) -> Area {
+        Area::new(Origin::new(x, y), Size::new(width, height))
+    }
+
+    #[test]
+    fn union_of_areas() {
+        assert_eq!(union(area(0, 0, 10, 10), area(5, 5, 10, 10)), area(0, 0, 15, 15));
+        assert_eq!(union(area(-5, 0, 1, 1), area(5, 5, 1, 1)), area(-5, 0, 11, 6));
+        assert_eq!(union(Area::default(), area(5, 5, 1, 1)), area(5, 5, 1, 1));
+        assert_eq!(union(area(5, 5, 1, 1), area(0, 0, 0, 10)), area(5, 5, 1, 1));
+    }
+
+    #[test]
+    fn intersection_of_areas() {
+        assert_eq!(intersection(area(0, 0, 10, 10), area(5, 5, 10, 10)),
+                   Some(area(5, 5, 5, 5)));
+        assert_eq!(intersection(area(0, 0, 10, 10), area(2, 2, 2, 2)),
+                   Some(area(2, 2, 2, 2)));
+        // Areas that only touch don't overlap.
+        assert_eq!(intersection(area(0, 0, 10, 10), area(10, 0, 10, 10)), None);
+        assert_eq!(intersection(area(0, 0, 10, 10), area(20, 20, 1, 1)), None);
+    }
+
+    #[test]
+    fn take() {
+        let output_box = area(100, 0, 100, 100);
+        let mut damage = Damage::default();
+        // Outputs that aren't tracked are always repainted completely.
+        assert_eq!(damage.take(&OutputHandle::default(), output_box, Some(2)),
+                   Some(output_box));
+
+        damage.add_output(OutputHandle::default());
+        let output = OutputHandle::default();
+        assert_eq!(damage.take(&output, output_box, Some(2)), Some(output_box));
+        assert_eq!(damage.take(&output, output_box, Some(2)), None);
+
+        // Damage outside of the output doesn't count.
+        damage.add_area(area(0, 0, 50, 50));
+        assert_eq!(damage.take(&output, output_box, Some(2)), None);
+
+        damage.add_area(area(90, 10, 20, 20));
+        // The whole output was drawn into the other buffer.
+        assert_eq!(damage.take(&output, output_box, Some(2)), Some(output_box));
+        damage.add_area(area(150, 50, 10, 10));
+        assert_eq!(damage.take(&output, output_box, Some(2)), Some(area(100, 10, 60, 50)));
+        assert_eq!(damage.take(&output, output_box, Some(2)), None);
+    }
+
+    #[test]
+    fn take_with_buffer_age() {
+        let output_box = area(0, 0, 100, 100);
+        let mut damage = Damage::default();
+        damage.add_output(OutputHandle::default());
+        let output = OutputHandle::default();
+        assert_eq!(damage.take(&output, output_box, Some(1)), Some(output_box));
+
+        // The buffer has the previous frame in it.
+        damage.add_area(area(10, 10, 10, 10));
+        assert_eq!(damage.take(&output, output_box, Some(1)), Some(area(10, 10, 10, 10)));
+        damage.add_area(area(50, 50, 10, 10));
+        assert_eq!(damage.take(&output, output_box, Some(1)), Some(area(50, 50, 10, 10)));
+
+        // The buffer is missing the previous frame as well.
+        damage.add_area(area(20, 20, 10, 10));
+        assert_eq!(damage.take(&output, output_box, Some(2)), Some(area(20, 20, 40, 40)));
+
+        // It isn't known what is in the buffer.
+        damage.add_area(area(20, 20, 10, 10));
+        assert_eq!(damage.take(&output, output_box, None), Some(output_box));
+        damage.add_area(area(20, 20, 10, 10));
+        assert_eq!(damage.take(&output, output_box, Some(0)), Some(output_box));
+        damage.add_area(area(20, 20, 10, 10));
+        assert_eq!(damage.take(&output, output_box, Some(3)), Some(output_box));
+
+        // Nothing changed, whatever is in the buffer.
+        assert_eq!(damage.take(&output, output_box, None), None);
+    }
+}
@@ -0,0 +1,298 @@
+//! Keeps track of whether the user is idle, i.e hasn't touched the keyboard
+//! or the pointer in a while.
+//!
+//! Lua is told with `idle::start` once each of its timeouts passed, and
+//! with `idle::stop` at the next activity. The outputs can be turned off
+//! after a while as well. Clients find out about idleness with the idle
+//! protocol, and can keep the user from becoming idle (e.g while playing a
+//! video) with the idle-inhibit protocol.
+
+use std::mem;
+use std::time::{Duration, Instant};
+
+use glib::{self, Continue, SourceId};
+use rlua::Value;
+use wlroots::{self, Compositor, CompositorHandle, IdleInhibitManagerHandler, IdleInhibitorHandle,
+              OutputHandle};
+
+use awesome::{self, LUA};
+use compositor::{self, Server};
+
+pub struct IdleInhibitManager;
+
+#[derive(Debug)]
+pub struct Idle {
+    /// When the keyboard or the pointer was last used.
+    last_activity: Instant,
+    /// After how long Lua is told that the user is idle, shortest first.
+    timeouts: Vec<Duration>,
+    /// How many of the timeouts passed since the last activity.
+    passed: usize,
+    /// After how long the outputs are turned off.
+    power_off: Option<Duration>,
+    /// The outputs that were turned off because the user is idle.
+    powered_off: Vec<OutputHandle>,
+    /// Whether the power off timeout passed since the last activity, even if
+    /// there were no outputs to turn off.
+    powered_off_done: bool,
+    /// Clients keeping the user from becoming idle.
+    inhibitors: Vec<IdleInhibitorHandle>,
+    /// The timer running to check if the user became idle, if there is one.
+    timer: Option<SourceId>
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Idle { last_activity: Instant::now(),
+               timeouts: Vec::new(),
+               passed: 0,
+               power_off: None,
+               powered_off: Vec::new(),
+               powered_off_done: false,
+               inhibitors: Vec::new(),
+               timer: None }
+    }
+}
+
+impl Idle {
+    pub fn inhibited(&self) -> bool {
+        !self.inhibitors.is_empty()
+    }
+
+    /// The outputs that were turned off because the user is idle.
+    ///
+    /// These are still in the layout, and are treated as if they were on.
+    pub fn powered_off(&self) -> &[OutputHandle] {
+        &self.powered_off
+    }
+
+    /// How long until something has to happen, if anything will.
+    fn next_deadline(&self) -> Option<Duration> {
+        if self.inhibited() {
+            return None
+        }
+        let timeout = self.timeouts.get(self.passed).cloned();
+        let power_off = if self.powered_off_done {
+            None
+        } else {
+            self.power_off
+        };
+        let deadline = match (timeout, power_off) {
+            (Some(timeout), Some(power_off)) => timeout.min(power_off),
+            (deadline, None) | (None, deadline) => deadline?
+        };
+        let elapsed = self.last_activity.elapsed();
+        Some(if deadline > elapsed {
+                 deadline - elapsed
+             } else {
+                 Duration::from_secs(0)
+             })
+    }
+
+    /// Starts a timer for the next deadline, unless there already is one.
+    ///
+    /// The timer doesn't have to be restarted on activity, it just finds out
+    /// that the deadline moved when it fires.
+    fn schedule(&mut self) {
+        if self.timer.is_some() {
+            return
+        }
+        let delay = match self.next_deadline() {
+            Some(delay) => delay,
+            None => return
+        };
+        let millis = delay.as_secs() * 1000 + (delay.subsec_nanos() / 1_000_000) as u64;
+        self.timer = Some(glib::timeout_add(millis as u32, || {
+                                                check_idle();
+                                                Continue(false)
+                                            }));
+    }
+
+    /// Restarts the timer, because the deadline might have come closer.
+    fn reschedule(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            glib::source_remove(timer);
+        }
+        self.schedule()
+    }
+}
+
+impl IdleInhibitManagerHandler for IdleInhibitManager {
+    fn new_inhibitor(&mut self, compositor: CompositorHandle, inhibitor: IdleInhibitorHandle) {
+        with_handles!([(compositor: {compositor})] => {
+            {
+                let server: &mut Server = (&mut *compositor).into();
+                server.idle.inhibitors.push(inhibitor);
+            }
+            update_idle_protocol(compositor);
+        }).unwrap();
+    }
+
+    fn inhibitor_destroyed(&mut self,
+                           compositor: CompositorHandle,
+                           inhibitor: IdleInhibitorHandle) {
+        with_handles!([(compositor: {compositor})] => {
+            {
+                let server: &mut Server = (&mut *compositor).into();
+                let idle = &mut server.idle;
+                idle.inhibitors.retain(|other| *other != inhibitor);
+                // The user is only idle once they've been inactive for a
+                // while after e.g the video stopped.
+                idle.last_activity = Instant::now();
+                idle.schedule();
+            }
+            update_idle_protocol(compositor);
+        }).unwrap();
+    }
+}
+
+/// Lets idle protocol clients know whether the user can become idle at all.
+fn update_idle_protocol(compositor: &mut Compositor) {
+    let (seat, inhibited) = {
+        let server: &mut Server = (&mut *compositor).into();
+        (server.seat.seat.clone(), server.idle.inhibited())
+    };
+    if let Some(idle) = compositor.idle.as_mut() {
+        with_handles!([(seat: {seat})] => {
+            idle.set_enabled(seat, !inhibited)
+        }).ok();
+    }
+}
+
+/// Resets the idle timer because the user used the keyboard or the
+/// pointer, waking everything up if they were idle.
+pub fn notify_activity(compositor: &mut Compositor) {
+    let seat = {
+        let server: &mut Server = (&mut *compositor).into();
+        server.seat.seat.clone()
+    };
+    if let Some(idle) = compositor.idle.as_mut() {
+        with_handles!([(seat: {seat})] => {
+            idle.notify_activity(seat)
+        }).ok();
+    }
+    let server: &mut Server = compositor.into();
+    let idle = &mut server.idle;
+    idle.last_activity = Instant::now();
+    let stopped = mem::replace(&mut idle.passed, 0) > 0;
+    idle.powered_off_done = false;
+    let powered_off = mem::replace(&mut idle.powered_off, Vec::new());
+    for output in &powered_off {
+        output.run(|output| output.enable(true)).ok();
+    }
+    idle.schedule();
+    if !stopped && powered_off.is_empty() {
+        return
+    }
+    // Lua can't be called while the compositor is borrowed.
+    let powered_on = !powered_off.is_empty();
+    glib::idle_add(move || {
+                       if powered_on {
+                           let compositor = wlroots::compositor_handle().unwrap();
+                           compositor::outputs_changed(compositor);
+                       }
+                       if stopped {
+                           emit_idle_signal("idle::stop", Value::Nil);
+                       }
+                       Continue(false)
+                   });
+}
+
+/// Called by the timer to see if the user became idle.
+fn check_idle() {
+    let started = dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref outputs,
+                     ref mut idle,
+                     ref mut captures,
+                     .. } = *server;
+        // The timer is done once this returns.
+        idle.timer = None;
+        if idle.inhibited() {
+            return Vec::new()
+        }
+        let elapsed = idle.last_activity.elapsed();
+        let mut started = Vec::new();
+        while idle.passed < idle.timeouts.len() && idle.timeouts[idle.passed] <= elapsed {
+            started.push(idle.timeouts[idle.passed]);
+            idle.passed += 1;
+        }
+        let power_off = idle.power_off.map(|power_off| power_off <= elapsed).unwrap_or(false);
+        if power_off && !idle.powered_off_done {
+            idle.powered_off_done = true;
+            // Outputs are left in the layout, so the screens stay the same.
+            for output in outputs {
+                let enabled = output.run(|output| {
+                                             let enabled = output.enabled();
+                                             output.enable(false);
+                                             enabled
+                                         });
+                if enabled.unwrap_or(false) {
+                    idle.powered_off.push(output.clone());
+                    // It won't draw the frame they are waiting for.
+                    captures.remove_output(output);
+                }
+            }
+        }
+        idle.schedule();
+        started
+    );
+    for timeout in started {
+        emit_idle_signal("idle::start", Value::Integer(timeout.as_secs() as _));
+    }
+}
+
+fn emit_idle_signal(name: &str, value: Value<'static>) {
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 if let Err(err) = awesome::global_emit_signal(&*lua, (name.into(), value)) {
+                     warn!("Could not emit {}: {:?}", name, err);
+                 }
+             });
+}
+
+/// Sets after how long Lua is told that the user is idle, from outside of a
+/// compositor callback (e.g from Lua).
+pub fn set_idle_timeouts(mut timeouts: Vec<Duration>) {
+    timeouts.sort();
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let idle = &mut server.idle;
+        // Timeouts that already passed since the last activity were
+        // reported before, only the new ones are reported right away.
+        let elapsed = idle.last_activity.elapsed();
+        let reported = idle.timeouts[..idle.passed].to_vec();
+        idle.passed = timeouts.iter()
+                              .take_while(|&&timeout| {
+                                              timeout <= elapsed && reported.contains(&timeout)
+                                          })
+                              .count();
+        idle.timeouts = timeouts;
+        idle.reschedule()
+    )
+}
+
+/// Sets after how long the outputs are turned off, from outside of a
+/// compositor callback (e.g from Lua).
+///
+/// If `None` the outputs are never turned off.
+pub fn set_power_off_timeout(timeout: Option<Duration>) {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.idle.power_off = timeout;
+        server.idle.reschedule()
+    )
+}
+
+/// Whether a client is keeping the user from becoming idle, from outside of
+/// a compositor callback (e.g from Lua).
+pub fn idle_inhibited() -> bool {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.idle.inhibited()
+    )
+}
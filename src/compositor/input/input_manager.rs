@@ -50,6 +50,6 @@ impl InputManagerHandler for InputManager {
             @cursor = {&server.cursor};
             cursor.attach_input_device(pointer.input_device())
         );
-        Some(Box::new(compositor::Pointer))
+        Some(Box::new(compositor::Pointer::default()))
     }
 }
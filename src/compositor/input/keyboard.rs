@@ -4,7 +4,7 @@ use wlroots::{key_events::KeyEvent, xkbcommon::xkb::{KEY_Escape, KEY_Super_L, KE
               KeyboardModifier, WLR_KEY_PRESSED};
 
 use awesome::{self, emit_object_signal, Objectable, LUA, ROOT_KEYS_HANDLE};
-use compositor::Server;
+use compositor::{self, Server};
 
 pub struct Keyboard;
 
@@ -17,6 +17,7 @@ impl KeyboardHandler for Keyboard {
     fn on_key(&mut self, compositor: CompositorHandle, keyboard: KeyboardHandle, event: &KeyEvent) {
         let modifiers = dehandle!(
             @compositor = {compositor};
+            compositor::notify_activity(compositor);
             if event.key_state() == WLR_KEY_PRESSED {
                 for key in event.pressed_keys() {
                     if key == KEY_Escape {
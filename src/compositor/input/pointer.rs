@@ -1,10 +1,54 @@
-use wlroots::{pointer_events::*, Capability, CompositorHandle, PointerHandle, PointerHandler,
-              WLR_BUTTON_RELEASED};
+use std::rc::Rc;
 
-use compositor::{Seat, Server};
+use wlroots::layer_shell::Layer;
+use wlroots::{pointer_events::*, wlr_axis_orientation, Capability, CompositorHandle, Cursor,
+              PointerHandle, PointerHandler, WLR_BUTTON_RELEASED};
+
+use awesome::{self, ButtonPress, LUA};
+use compositor::{self, Action, Damage, LayerSurface, Seat, Server, View};
+
+/// How far a touchpad has to scroll to count as a step of a scroll wheel.
+const SCROLL_STEP: f64 = 10.0;
 
 #[derive(Debug, Default)]
-pub struct Pointer;
+pub struct Pointer {
+    /// How far a touchpad scrolled horizontally and vertically since the
+    /// last step that was given to the button bindings.
+    scrolled: (f64, f64)
+}
+
+impl Pointer {
+    /// How many steps of a scroll wheel the axis event is, negative when
+    /// scrolling up or left.
+    ///
+    /// Touchpads scroll smoothly, so their steps are counted once they
+    /// scrolled far enough.
+    fn scroll_steps(&mut self, event: &AxisEvent) -> i32 {
+        let scrolled = if is_vertical(event) {
+            &mut self.scrolled.1
+        } else {
+            &mut self.scrolled.0
+        };
+        scroll_steps(scrolled, event.delta_discrete(), event.delta())
+    }
+}
+
+/// Counts the steps of a scroll, keeping what is left over in `scrolled`.
+fn scroll_steps(scrolled: &mut f64, delta_discrete: i32, delta: f64) -> i32 {
+    if delta_discrete != 0 {
+        *scrolled = 0.0;
+        return delta_discrete
+    }
+    // The fingers were lifted from the touchpad.
+    if delta == 0.0 {
+        *scrolled = 0.0;
+        return 0
+    }
+    *scrolled += delta;
+    let steps = (*scrolled / SCROLL_STEP).trunc();
+    *scrolled -= steps * SCROLL_STEP;
+    steps as i32
+}
 
 impl PointerHandler for Pointer {
     fn on_motion_absolute(&mut self,
@@ -13,55 +57,113 @@ impl PointerHandler for Pointer {
                           event: &AbsoluteMotionEvent) {
         dehandle!(
             @compositor = {compositor};
+            compositor::notify_activity(compositor);
             let server: &mut Server = compositor.data.downcast_mut().unwrap();
             let Server { ref cursor,
                          ref mut xcursor_manager,
                          ref mut seat,
+                         ref layer_surfaces,
+                         ref mut unmanaged,
                          ref mut views,
+                         ref mut damage,
                          .. } = *server;
             @cursor = {cursor};
+            let before = cursor.coords();
             let (x, y) = event.pos();
             cursor.warp_absolute(event.device(), x, y);
             seat.update_cursor_position(cursor,
                                         xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
                                         views,
-                                        Some(event.time_msec())));
+                                        Some(event.time_msec()));
+            seat.move_drag_icons(cursor, damage);
+            damage_motion(damage, seat, before, cursor.coords()));
     }
 
     fn on_motion(&mut self, compositor: CompositorHandle, _: PointerHandle, event: &MotionEvent) {
         dehandle!(
             @compositor = {compositor};
+            compositor::notify_activity(compositor);
             let server: &mut Server = compositor.into();
             let Server { ref cursor,
                          ref mut xcursor_manager,
                          ref mut seat,
+                         ref layer_surfaces,
+                         ref mut unmanaged,
                          ref mut views,
+                         ref mut damage,
                          .. } = *server;
             @cursor = {cursor};
+            let before = cursor.coords();
             let (x, y) = event.delta();
             cursor.move_to(event.device(), x, y);
             seat.update_cursor_position(cursor,
                                         xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
                                         views,
-                                        Some(event.time_msec())));
+                                        Some(event.time_msec()));
+            seat.move_drag_icons(cursor, damage);
+            damage_motion(damage, seat, before, cursor.coords()));
     }
 
     fn on_button(&mut self, compositor: CompositorHandle, _: PointerHandle, event: &ButtonEvent) {
-        dehandle!(
+        let press = dehandle!(
             @compositor = {compositor};
+            compositor::notify_activity(compositor);
             let server: &mut Server = compositor.into();
             let Server { ref cursor,
+                         ref layer_surfaces,
+                         ref mut unmanaged,
                          ref mut views,
                          ref mut seat,
                          .. } = *server;
             @cursor = {cursor};
-            if event.state() == WLR_BUTTON_RELEASED {
+            let (lx, ly) = cursor.coords();
+            let pressed = event.state() != WLR_BUTTON_RELEASED;
+            let modifiers = seat.modifiers();
+            let press = awesome_button(event.button()).map(|button| {
+                                                               ButtonPress { button,
+                                                                             pressed,
+                                                                             x: lx,
+                                                                             y: ly,
+                                                                             modifiers,
+                                                                             view: None }
+                                                           });
+            if !pressed {
                 seat.action = None;
                 seat.send_button(event);
-                return
+                if let Some(view) = client_at(layer_surfaces, unmanaged, views, cursor) {
+                    return press.map(|press| ButtonPress { view: Some(view), ..press })
+                }
+                if !lua_gets_button(layer_surfaces, unmanaged, views, cursor) {
+                    return None
+                }
+                return press
+            };
+
+            let above = [Layer::Overlay, Layer::Top];
+            if let (Some(layer_surface), _, _, _) =
+                Seat::layer_surface_at(layer_surfaces, &above, cursor) {
+                if layer_surface.keyboard_interactive() {
+                    seat.focus_layer(layer_surface);
+                }
+                seat.send_button(event);
+                return None
             };
 
-            if let (Some(view), _, _, _) = Seat::view_at_pointer(views, cursor) {
+            let (view, _, _, _) = Seat::view_at_pointer(unmanaged, views, cursor);
+            // Unmanaged windows (e.g menus) must never take the focus.
+            if view.as_ref().map(|view| unmanaged.contains(view)).unwrap_or(false) {
+                seat.send_button(event);
+                return None
+            }
+            // Drawins are above the views.
+            if drawin_at(lx, ly) {
+                return press
+            }
+            if let Some(view) = view {
                 seat.focus_view(view.clone(), views);
 
                 let meta_held_down = seat.meta;
@@ -69,9 +171,66 @@ impl PointerHandler for Pointer {
                     seat.move_view(cursor, &view, None);
                 }
                 seat.send_button(event);
-            } else {
-                seat.clear_focus();
-            });
+                return press.map(|press| ButtonPress { view: Some(view), ..press })
+            }
+            seat.clear_focus();
+            let below = [Layer::Bottom, Layer::Background];
+            if let (Some(_), _, _, _) = Seat::layer_surface_at(layer_surfaces, &below, cursor) {
+                seat.send_button(event);
+                return None
+            }
+            press
+        );
+        emit_button_bindings(press.into_iter().collect());
+    }
+
+    fn on_axis(&mut self, compositor: CompositorHandle, _: PointerHandle, event: &AxisEvent) {
+        let presses = dehandle!(
+            @compositor = {compositor};
+            compositor::notify_activity(compositor);
+            let server: &mut Server = compositor.into();
+            let Server { ref cursor,
+                         ref layer_surfaces,
+                         ref mut unmanaged,
+                         ref mut views,
+                         ref mut seat,
+                         .. } = *server;
+            @cursor = {cursor};
+            let view = client_at(layer_surfaces, unmanaged, views, cursor);
+            if view.is_none() && !lua_gets_button(layer_surfaces, unmanaged, views, cursor) {
+                // The surface under the pointer has the pointer focus.
+                seat.send_axis(event);
+                return Vec::new()
+            }
+            // The client scrolls too, not just its bindings.
+            if view.is_some() {
+                seat.send_axis(event);
+            }
+            let steps = self.scroll_steps(event);
+            // Awesome scrolls with buttons 4 and 5 (up and down), or 6 and 7
+            // (left and right).
+            let button = match (is_vertical(event), steps < 0) {
+                (true, true) => 4,
+                (true, false) => 5,
+                (false, true) => 6,
+                (false, false) => 7
+            };
+            let (lx, ly) = cursor.coords();
+            let modifiers = seat.modifiers();
+            let mut presses = Vec::new();
+            for _ in 0..steps.abs() {
+                for &pressed in &[true, false] {
+                    presses.push(ButtonPress { button,
+                                               pressed,
+                                               x: lx,
+                                               y: ly,
+                                               modifiers,
+                                               view: view.clone() });
+                }
+            }
+            presses
+        );
+        emit_button_bindings(presses);
     }
 
     fn destroyed(&mut self, compositor: CompositorHandle, pointer: PointerHandle) {
@@ -96,3 +255,132 @@ impl PointerHandler for Pointer {
         }).unwrap();
     }
 }
+
+/// Damages what moving the pointer changed on the outputs.
+fn damage_motion(damage: &mut Damage, seat: &Seat, before: (f64, f64), after: (f64, f64)) {
+    damage.add_cursor(before.0, before.1);
+    damage.add_cursor(after.0, after.1);
+    if let Some(Action::Moving { .. }) = seat.action {
+        if let Some(ref view) = seat.focused {
+            view.damage(damage);
+        }
+    }
+}
+
+/// The number Awesome knows a button by, if it has one.
+fn awesome_button(button: u32) -> Option<u32> {
+    match button {
+        BTN_LEFT => Some(1),
+        BTN_MIDDLE => Some(2),
+        BTN_RIGHT => Some(3),
+        BTN_SIDE => Some(8),
+        BTN_EXTRA => Some(9),
+        _ => None
+    }
+}
+
+/// The client under the pointer, unless something else is drawn above it.
+///
+/// Its client bindings get the buttons along with it.
+fn client_at(layer_surfaces: &[Rc<LayerSurface>],
+             unmanaged: &mut [Rc<View>],
+             views: &mut [Rc<View>],
+             cursor: &mut Cursor)
+             -> Option<Rc<View>> {
+    let above = [Layer::Overlay, Layer::Top];
+    if let (Some(_), _, _, _) = Seat::layer_surface_at(layer_surfaces, &above, cursor) {
+        return None
+    }
+    let (view, _, _, _) = Seat::view_at_pointer(unmanaged, views, cursor);
+    let view = view.filter(|view| !unmanaged.contains(view))?;
+    let (lx, ly) = cursor.coords();
+    if drawin_at(lx, ly) {
+        return None
+    }
+    Some(view)
+}
+
+/// Whether the button bindings of Lua get a button rather than a client,
+/// i.e whether the pointer is over a drawin or over nothing at all.
+fn lua_gets_button(layer_surfaces: &[Rc<LayerSurface>],
+                   unmanaged: &mut [Rc<View>],
+                   views: &mut [Rc<View>],
+                   cursor: &mut Cursor)
+                   -> bool {
+    let above = [Layer::Overlay, Layer::Top];
+    if let (Some(_), _, _, _) = Seat::layer_surface_at(layer_surfaces, &above, cursor) {
+        return false
+    }
+    let (view, _, _, _) = Seat::view_at_pointer(unmanaged, views, cursor);
+    if view.as_ref().map(|view| unmanaged.contains(view)).unwrap_or(false) {
+        return false
+    }
+    let (lx, ly) = cursor.coords();
+    if drawin_at(lx, ly) {
+        return true
+    }
+    let below = [Layer::Bottom, Layer::Background];
+    view.is_none() && Seat::layer_surface_at(layer_surfaces, &below, cursor).0.is_none()
+}
+
+fn drawin_at(lx: f64, ly: f64) -> bool {
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 match awesome::drawin_at(&*lua, lx, ly) {
+                     Ok(drawin) => drawin.is_some(),
+                     Err(err) => {
+                         warn!("Could not find the drawin under the pointer: {:?}", err);
+                         false
+                     }
+                 }
+             })
+}
+
+fn is_vertical(event: &AxisEvent) -> bool {
+    event.orientation() == wlr_axis_orientation::WLR_AXIS_ORIENTATION_VERTICAL
+}
+
+/// Emits the button bindings of Lua, once the compositor isn't borrowed
+/// anymore.
+fn emit_button_bindings(presses: Vec<ButtonPress>) {
+    if presses.is_empty() {
+        return
+    }
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 for press in presses {
+                     if let Err(err) = awesome::emit_button_bindings(&*lua, &press) {
+                         warn!("Could not emit the bindings of button {}: {:?}", press.button, err);
+                     }
+                 }
+             });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wheel_steps() {
+        let mut scrolled = 4.0;
+        assert_eq!(scroll_steps(&mut scrolled, -1, -10.0), -1);
+        assert_eq!(scrolled, 0.0);
+        assert_eq!(scroll_steps(&mut scrolled, 2, 30.0), 2);
+    }
+
+    #[test]
+    fn touchpad_steps() {
+        let mut scrolled = 0.0;
+        assert_eq!(scroll_steps(&mut scrolled, 0, 4.0), 0);
+        assert_eq!(scroll_steps(&mut scrolled, 0, 4.0), 0);
+        assert_eq!(scroll_steps(&mut scrolled, 0, 4.0), 1);
+        assert_eq!(scrolled, 2.0);
+        assert_eq!(scroll_steps(&mut scrolled, 0, 25.0), 2);
+        assert_eq!(scrolled, 7.0);
+        assert_eq!(scroll_steps(&mut scrolled, 0, -20.0), -1);
+        assert_eq!(scrolled, -3.0);
+        // Lifting the fingers forgets what was left over.
+        assert_eq!(scroll_steps(&mut scrolled, 0, 0.0), 0);
+        assert_eq!(scrolled, 0.0);
+    }
+}
@@ -1,87 +1,160 @@
+mod backend;
 mod cursor;
+mod damage;
+mod idle;
 mod input;
 mod output;
+mod screencopy;
 mod seat;
+mod selection;
 mod shells;
 mod view;
+mod wallpaper;
 mod xwayland;
 
+pub use self::backend::*;
 pub use self::cursor::*;
+pub use self::damage::*;
+pub use self::idle::*;
 pub use self::input::*;
 pub use self::output::*;
+pub use self::screencopy::*;
 pub use self::seat::*;
+pub use self::selection::*;
 pub use self::shells::*;
 pub use self::view::*;
+pub use self::wallpaper::*;
 pub use self::xwayland::*;
 
 use wlroots::{self, Compositor, CompositorBuilder, Cursor, CursorHandle, KeyboardHandle,
               OutputHandle, OutputLayout, OutputLayoutHandle, PointerHandle, XCursorManager};
 
+use std::collections::HashMap;
 use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Server {
+    pub backend: Backend,
     pub xcursor_manager: XCursorManager,
+    /// The theme `xcursor_manager` was loaded from.
+    pub cursor_theme: CursorTheme,
     pub layout: OutputLayoutHandle,
     pub seat: Seat,
     pub cursor: CursorHandle,
     pub keyboards: Vec<KeyboardHandle>,
     pub pointers: Vec<PointerHandle>,
     pub outputs: Vec<OutputHandle>,
-    pub views: Vec<Rc<View>>
+    /// How outputs are set up, keyed by their name (e.g "DP-1").
+    pub output_configs: HashMap<String, OutputConfig>,
+    /// Saved arrangements of the outputs, for when the same set of outputs
+    /// is connected again.
+    pub output_profiles: OutputProfiles,
+    /// Gamma ramps set from Lua, keyed by output name.
+    pub gamma: HashMap<String, GammaRamps>,
+    /// Outputs whose gamma ramps are controlled by a client.
+    pub gamma_controlled: Vec<OutputHandle>,
+    pub damage: Damage,
+    /// Captures of the outputs Lua asked for.
+    pub captures: Captures,
+    /// Whether the user is idle, and what to do when they are.
+    pub idle: Idle,
+    pub views: Vec<Rc<View>>,
+    /// Override redirect XWayland windows, e.g menus and tooltips.
+    ///
+    /// These are stacked above everything else and are never focused.
+    pub unmanaged: Vec<Rc<View>>,
+    /// Panels, docks, backgrounds and the like from layer shell clients.
+    pub layer_surfaces: Vec<Rc<LayerSurface>>,
+    pub wallpaper: Wallpaper,
+    pub selection: Selection,
+    pub xwayland_ready: bool
 }
 
 impl Default for Server {
     fn default() -> Server {
-        let xcursor_manager =
-            XCursorManager::create("default".to_string(), 24).expect("Could not create xcursor \
-                                                                      manager");
+        let cursor_theme = CursorTheme::default();
+        let xcursor_manager = cursor_theme.create_manager()
+                                          .expect("Could not create xcursor manager");
         xcursor_manager.load(1.0);
-        Server { xcursor_manager,
+        Server { backend: Backend::default(),
+                 xcursor_manager,
+                 cursor_theme,
                  layout: OutputLayoutHandle::default(),
                  seat: Seat::default(),
                  cursor: CursorHandle::default(),
                  keyboards: Vec::default(),
                  pointers: Vec::default(),
                  outputs: Vec::default(),
-                 views: Vec::default() }
+                 output_configs: HashMap::default(),
+                 output_profiles: OutputProfiles::default(),
+                 gamma: HashMap::default(),
+                 gamma_controlled: Vec::default(),
+                 damage: Damage::default(),
+                 captures: Captures::default(),
+                 idle: Idle::default(),
+                 views: Vec::default(),
+                 unmanaged: Vec::default(),
+                 layer_surfaces: Vec::default(),
+                 wallpaper: Wallpaper::default(),
+                 selection: Selection::default(),
+                 xwayland_ready: false }
     }
 }
 
 impl Server {
-    pub fn new(layout: OutputLayoutHandle, cursor: CursorHandle) -> Self {
-        let mut xcursor_manager =
-            XCursorManager::create("default".to_string(), 24).expect("Could not create xcursor \
-                                                                      manager");
+    pub fn new(backend: Backend, layout: OutputLayoutHandle, cursor: CursorHandle) -> Self {
+        let cursor_theme = CursorTheme::from_env();
+        cursor_theme.export();
+        let mut xcursor_manager = cursor_theme.create_manager()
+                                              .expect("Could not create xcursor manager");
         xcursor_manager.load(1.0);
-        cursor.run(|c| xcursor_manager.set_cursor_image("left_ptr".to_string(), c))
+        cursor.run(|c| xcursor_manager.set_cursor_image(DEFAULT_CURSOR.to_string(), c))
               .unwrap();
 
-        Server { xcursor_manager,
+        Server { backend,
+                 xcursor_manager,
+                 cursor_theme,
                  layout,
                  cursor,
+                 output_profiles: OutputProfiles::load(),
                  ..Server::default() }
     }
 }
 
 compositor_data!(Server);
 
-pub fn init() -> Compositor {
+pub fn init(backend: Backend, xwayland: XWaylandMode) -> Compositor {
+    backend.export();
     let layout = OutputLayout::create(Box::new(OutputLayoutManager::new()));
     let cursor = Cursor::create(Box::new(CursorManager::new()));
-    let mut compositor = CompositorBuilder::new().gles2(true)
-                                                 .data_device(true)
-                                                 .output_manager(Box::new(OutputManager::new()))
-                                                 .input_manager(Box::new(InputManager::new()))
-                                                 .xwayland(Box::new(XWaylandManager::new()))
-                                                 .xdg_shell_v6_manager(Box::new(XdgV6ShellManager))
-                                                 .custom_terminate(|| ::awesome::lua::terminate())
-                                                 .build_auto(Server::new(layout, cursor));
+    let mut builder = CompositorBuilder::new().gles2(true)
+                                              .data_device(true)
+                                              .primary_selection(true)
+                                              .idle(true)
+                                              .idle_inhibit_manager(Box::new(IdleInhibitManager))
+                                              .output_manager(Box::new(OutputManager::new()))
+                                              .gamma_control_manager(Box::new(GammaControlManager))
+                                              .screencopy_manager(Box::new(ScreencopyManager))
+                                              .input_manager(Box::new(InputManager::new()))
+                                              .xdg_shell_manager(Box::new(XdgShellManager))
+                                              .xdg_shell_v6_manager(Box::new(XdgV6ShellManager))
+                                              .layer_shell_manager(Box::new(LayerShellManager));
+    builder = match xwayland {
+        XWaylandMode::Disabled => builder,
+        XWaylandMode::Eager => builder.xwayland(Box::new(XWaylandManager::new())),
+        XWaylandMode::Lazy => {
+            builder.xwayland_lazy(true)
+                   .xwayland(Box::new(XWaylandManager::new()))
+        }
+    };
+    let mut compositor = builder.custom_terminate(|| ::awesome::lua::terminate())
+                                .build_auto(Server::new(backend, layout, cursor));
     // NOTE We need to create this afterwards because it needs the compositor
     // running to announce the seat.
     let seat = wlroots::Seat::create(&mut compositor,
                                      "seat0".into(),
                                      Box::new(SeatManager::new()));
+    share_selections(&mut compositor, &seat);
     {
         let server: &mut Server = (&mut compositor).into();
         server.seat = Seat::new(seat);
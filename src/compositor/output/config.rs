@@ -0,0 +1,248 @@
+//! User configuration of outputs, e.g their resolution or where they are in
+//! the layout.
+
+use wlroots::{self, Area, Origin, OutputHandle, OutputTransform};
+
+use compositor::{self, Server};
+
+/// A resolution, with an optional refresh rate in mHz.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ModeConfig {
+    pub width: i32,
+    pub height: i32,
+    pub refresh: Option<i32>
+}
+
+/// How an output should be set up.
+///
+/// Anything that is `None` is left up to the compositor.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutputConfig {
+    pub enabled: Option<bool>,
+    pub mode: Option<ModeConfig>,
+    pub scale: Option<f32>,
+    pub transform: Option<OutputTransform>,
+    /// Where the output is in the layout.
+    pub position: Option<Origin>
+}
+
+impl OutputConfig {
+    /// Overrides this configuration with everything that is set in `other`.
+    pub fn merge(&mut self, other: OutputConfig) {
+        self.enabled = other.enabled.or(self.enabled);
+        self.mode = other.mode.or(self.mode);
+        self.scale = other.scale.or(self.scale);
+        self.transform = other.transform.or(self.transform);
+        self.position = other.position.or(self.position);
+    }
+
+    /// How the output is set up right now.
+    ///
+    /// `powered_off` is whether the output was only turned off because the
+    /// user is idle, in which case it still counts as enabled.
+    pub fn current(layout: &mut wlroots::OutputLayout,
+                   output: &wlroots::Output,
+                   powered_off: bool)
+                   -> Self {
+        let enabled = output.enabled() || powered_off;
+        let mode = output.current_mode().map(|mode| {
+                                                 let (width, height) = mode.dimensions();
+                                                 ModeConfig { width,
+                                                              height,
+                                                              refresh: Some(mode.refresh()) }
+                                             });
+        let position = if enabled {
+            Some(layout.get_box(Some(output)).origin)
+        } else {
+            None
+        };
+        OutputConfig { enabled: Some(enabled),
+                       mode,
+                       scale: Some(output.scale()),
+                       transform: Some(output.get_transform()),
+                       position }
+    }
+
+    /// Applies the configuration to an output, placing it in the layout.
+    ///
+    /// Disabled outputs are taken out of the layout. Fails if the backend
+    /// refused the mode, the rest of the configuration is still applied.
+    pub fn apply(&self,
+                 layout: &mut wlroots::OutputLayout,
+                 output: &mut wlroots::Output)
+                 -> Result<(), String> {
+        if self.enabled == Some(false) {
+            output.enable(false);
+            layout.remove(output);
+            return Ok(())
+        }
+        output.enable(true);
+        let result = match self.mode {
+            Some(mode) => apply_mode(output, mode),
+            None => Ok(())
+        };
+        if let Some(scale) = self.scale {
+            output.set_scale(scale);
+        }
+        if let Some(transform) = self.transform {
+            output.set_transform(transform);
+        }
+        match self.position {
+            Some(position) => layout.add(output, position),
+            None => layout.add_auto(output)
+        }
+        result
+    }
+}
+
+/// Parses an output transform from its name, e.g "90" or "flipped-180".
+pub fn transform_from_name(name: &str) -> Result<OutputTransform, String> {
+    use wlroots::OutputTransform::*;
+    match name {
+        "normal" | "0" => Ok(WL_OUTPUT_TRANSFORM_NORMAL),
+        "90" => Ok(WL_OUTPUT_TRANSFORM_90),
+        "180" => Ok(WL_OUTPUT_TRANSFORM_180),
+        "270" => Ok(WL_OUTPUT_TRANSFORM_270),
+        "flipped" => Ok(WL_OUTPUT_TRANSFORM_FLIPPED),
+        "flipped-90" => Ok(WL_OUTPUT_TRANSFORM_FLIPPED_90),
+        "flipped-180" => Ok(WL_OUTPUT_TRANSFORM_FLIPPED_180),
+        "flipped-270" => Ok(WL_OUTPUT_TRANSFORM_FLIPPED_270),
+        name => Err(format!("Unknown output transform \"{}\"", name))
+    }
+}
+
+/// The name of an output transform, as understood by `transform_from_name`.
+pub fn transform_name(transform: OutputTransform) -> &'static str {
+    use wlroots::OutputTransform::*;
+    match transform {
+        WL_OUTPUT_TRANSFORM_NORMAL => "normal",
+        WL_OUTPUT_TRANSFORM_90 => "90",
+        WL_OUTPUT_TRANSFORM_180 => "180",
+        WL_OUTPUT_TRANSFORM_270 => "270",
+        WL_OUTPUT_TRANSFORM_FLIPPED => "flipped",
+        WL_OUTPUT_TRANSFORM_FLIPPED_90 => "flipped-90",
+        WL_OUTPUT_TRANSFORM_FLIPPED_180 => "flipped-180",
+        WL_OUTPUT_TRANSFORM_FLIPPED_270 => "flipped-270"
+    }
+}
+
+/// Sets the mode of the output that best matches the configured one.
+///
+/// If the output doesn't advertise the resolution a custom mode is used.
+fn apply_mode(output: &mut wlroots::Output, mode: ModeConfig) -> Result<(), String> {
+    let ModeConfig { width,
+                     height,
+                     refresh } = mode;
+    let best_mode = output.modes()
+                          .into_iter()
+                          .filter(|output_mode| output_mode.dimensions() == (width, height))
+                          .min_by_key(|output_mode| match refresh {
+                                          Some(refresh) => (output_mode.refresh() - refresh).abs(),
+                                          None => -output_mode.refresh()
+                                      });
+    let applied = match best_mode {
+        Some(output_mode) => output.set_mode(output_mode),
+        None => {
+            warn!("{} has no {}x{} mode, trying a custom one", output.name(), width, height);
+            output.set_custom_mode(width, height, refresh.unwrap_or(0))
+        }
+    };
+    if applied {
+        Ok(())
+    } else {
+        Err(format!("{} refused the {}x{} mode", output.name(), width, height))
+    }
+}
+
+/// Rearranges everything after the outputs changed.
+///
+/// Returns where each output is in the layout, along with its workarea.
+pub fn rearrange_outputs(compositor: &mut wlroots::Compositor) -> Vec<(OutputHandle, Area, Area)> {
+    let server: &mut Server = compositor.into();
+    // The outputs might have been rescaled.
+    compositor::load_cursor_theme(server);
+    let Server { ref mut layout,
+                 ref outputs,
+                 ref layer_surfaces,
+                 ref mut damage,
+                 ref mut wallpaper,
+                 ref idle,
+                 .. } = *server;
+    // Outputs might have moved around in the layout.
+    damage.add_whole();
+    wallpaper.invalidate();
+    let boxes = compositor::output_boxes(layout, outputs, idle.powered_off());
+    boxes.into_iter()
+         .map(|(output, geometry)| {
+                  let workarea = compositor::arrange_layers(layout, &output, layer_surfaces);
+                  (output, geometry, workarea)
+              })
+         .collect()
+}
+
+/// The output with the given name, if it is connected.
+pub fn find_output(server: &Server, name: &str) -> Option<OutputHandle> {
+    server.outputs
+          .iter()
+          .find(|output| output.run(|output| output.name() == name).unwrap_or(false))
+          .cloned()
+}
+
+/// Stores the configuration of the output with the given name, applying it
+/// right away if the output exists.
+///
+/// If `replace` is false only what is set in `config` is changed.
+pub fn set_output_config(server: &mut Server,
+                         name: &str,
+                         config: OutputConfig,
+                         replace: bool)
+                         -> Result<(), String> {
+    let config = {
+        let stored = server.output_configs
+                           .entry(name.to_string())
+                           .or_insert_with(OutputConfig::default);
+        if replace {
+            *stored = config;
+        } else {
+            stored.merge(config);
+        }
+        stored.clone()
+    };
+    apply_output_config(server, name, &config)
+}
+
+/// Applies a configuration to the output with the given name if it exists,
+/// without storing it.
+pub fn apply_output_config(server: &mut Server,
+                           name: &str,
+                           config: &OutputConfig)
+                           -> Result<(), String> {
+    match find_output(server, name) {
+        Some(output) => {
+            with_handles!([(layout: {&mut server.layout}), (output: {output})] => {
+                config.apply(layout, output)
+            }).unwrap()
+        }
+        None => Ok(())
+    }
+}
+
+/// Changes the configuration of the output with the given name from outside
+/// of a compositor callback, e.g from Lua.
+///
+/// Returns the rearranged outputs, for Lua to update its screens with.
+pub fn configure_output(name: &str,
+                        config: OutputConfig,
+                        replace: bool)
+                        -> Vec<(OutputHandle, Area, Area)> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        {
+            let server: &mut Server = (&mut *compositor).into();
+            if let Err(err) = set_output_config(server, name, config, replace) {
+                warn!("Could not configure {}: {}", name, err);
+            }
+        }
+        rearrange_outputs(compositor)
+    )
+}
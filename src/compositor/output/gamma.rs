@@ -0,0 +1,216 @@
+//! Gamma ramps of the outputs, e.g to make the colours warmer at night.
+//!
+//! They can be set by clients (e.g gammastep or wlsunset) using the
+//! wlr-gamma-control protocol, or from Lua. What Lua set is restored once
+//! the client lets go of the output.
+
+use wlroots::gamma_control::GammaControlManagerHandler;
+use wlroots::{self, CompositorHandle, OutputHandle};
+
+use compositor::Server;
+
+/// The colour temperature of daylight, which leaves the colours as they
+/// are.
+pub const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// A gamma ramp for each colour channel, all of the same size.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GammaRamps {
+    pub red: Vec<u16>,
+    pub green: Vec<u16>,
+    pub blue: Vec<u16>
+}
+
+impl GammaRamps {
+    /// Ramps that tint the output to the colour temperature (in Kelvin) and
+    /// scale it by the brightness (from 0 to 1).
+    ///
+    /// `size` is how many entries the ramps of the output have.
+    pub fn from_temperature(temperature: u32, brightness: f64, size: usize) -> Self {
+        let (red, green, blue) = whitepoint(temperature);
+        let ramp = |factor: f64| -> Vec<u16> {
+            (0..size).map(|index| {
+                         let value = index as f64 / (size.max(2) - 1) as f64;
+                         (value * factor * brightness * 65535.0).max(0.0).min(65535.0) as u16
+                     })
+                     .collect()
+        };
+        GammaRamps { red: ramp(red),
+                     green: ramp(green),
+                     blue: ramp(blue) }
+    }
+
+    pub fn validate(&self, size: usize) -> Result<(), String> {
+        if self.red.len() != size || self.green.len() != size || self.blue.len() != size {
+            return Err(format!("Gamma ramps must have {} entries", size))
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, output: &mut wlroots::Output) {
+        output.set_gamma(&self.red, &self.green, &self.blue);
+    }
+}
+
+/// How much of each channel is left at the colour temperature, from 0 to 1.
+///
+/// Nothing is taken away at the neutral temperature.
+fn whitepoint(temperature: u32) -> (f64, f64, f64) {
+    let (red, green, blue) = black_body(temperature);
+    let (neutral_red, neutral_green, neutral_blue) = black_body(NEUTRAL_TEMPERATURE);
+    let clamp = |value: f64| value.max(0.0).min(1.0);
+    (clamp(red / neutral_red), clamp(green / neutral_green), clamp(blue / neutral_blue))
+}
+
+/// Approximates the colour of a black body at the temperature, as redshift
+/// does.
+fn black_body(temperature: u32) -> (f64, f64, f64) {
+    let temperature = (temperature.max(1000).min(40000) as f64) / 100.0;
+    let red = if temperature <= 66.0 {
+        1.0
+    } else {
+        1.292_936_186 * (temperature - 60.0).powf(-0.133_204_759_2)
+    };
+    let green = if temperature <= 66.0 {
+        0.390_081_578_8 * temperature.ln() - 0.631_841_443_8
+    } else {
+        1.129_890_861 * (temperature - 60.0).powf(-0.075_514_849_2)
+    };
+    let blue = if temperature >= 66.0 {
+        1.0
+    } else if temperature <= 19.0 {
+        0.0
+    } else {
+        0.543_206_789_1 * (temperature - 10.0).ln() - 1.196_254_089
+    };
+    let clamp = |value: f64| value.max(0.0).min(1.0);
+    (clamp(red), clamp(green), clamp(blue))
+}
+
+pub struct GammaControlManager;
+
+impl GammaControlManagerHandler for GammaControlManager {
+    fn set_gamma(&mut self,
+                 compositor: CompositorHandle,
+                 output: OutputHandle,
+                 red: &[u16],
+                 green: &[u16],
+                 blue: &[u16]) {
+        with_handles!([(compositor: {compositor}), (output: {&output})] => {
+            let server: &mut Server = compositor.into();
+            let weak_reference = output.weak_reference();
+            if !server.gamma_controlled.contains(&weak_reference) {
+                server.gamma_controlled.push(weak_reference);
+            }
+            output.set_gamma(red, green, blue);
+        }).unwrap();
+    }
+
+    fn destroyed(&mut self, compositor: CompositorHandle, output: OutputHandle) {
+        with_handles!([(compositor: {compositor}), (output: {&output})] => {
+            let server: &mut Server = compositor.into();
+            server.gamma_controlled.retain(|other| *other != output.weak_reference());
+            restore_gamma(server, output);
+        }).ok();
+    }
+}
+
+/// Sets the gamma ramps Lua chose for the output, or the default ones.
+pub fn restore_gamma(server: &mut Server, output: &mut wlroots::Output) {
+    match server.gamma.get(&output.name()) {
+        Some(ramps) => ramps.apply(output),
+        None => output.reset_gamma()
+    }
+}
+
+/// How many entries the gamma ramps of the output with the given name have,
+/// from outside of a compositor callback (e.g from Lua).
+pub fn gamma_size(name: &str) -> Option<usize> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.outputs
+              .iter()
+              .filter_map(|output| output.run(|output| (output.name(), output.gamma_size())).ok())
+              .find(|&(ref other, _)| other == name)
+              .map(|(_, size)| size)
+    )
+}
+
+/// Sets the gamma ramps of the output with the given name, from outside of
+/// a compositor callback (e.g from Lua).
+///
+/// If `ramps` is `None` the default ramps are restored. The ramps are kept
+/// for when the output is plugged in again, or a client stops changing them.
+pub fn set_gamma(name: &str, ramps: Option<GammaRamps>) {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        match ramps {
+            Some(ramps) => server.gamma.insert(name.to_string(), ramps),
+            None => server.gamma.remove(name)
+        };
+        let output = server.outputs
+                           .iter()
+                           .find(|output| {
+                                     output.run(|output| output.name() == name).unwrap_or(false)
+                                 })
+                           .cloned();
+        // A client is in control, what Lua set is restored once it is done.
+        let output = output.filter(|output| !server.gamma_controlled.contains(output));
+        if let Some(output) = output {
+            with_handles!([(output: {output})] => {
+                restore_gamma(server, output)
+            }).ok();
+        }
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn neutral_whitepoint() {
+        assert_eq!(whitepoint(NEUTRAL_TEMPERATURE), (1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn warm_whitepoints() {
+        let (red, green, blue) = whitepoint(3000);
+        assert_eq!(red, 1.0);
+        assert!(green < 1.0 && blue < green);
+        let (_, warmer_green, warmer_blue) = whitepoint(2000);
+        assert!(warmer_green < green && warmer_blue < blue);
+        assert_eq!(whitepoint(1000).2, 0.0);
+        // Temperatures are clamped to what the approximation works for.
+        assert_eq!(whitepoint(0), whitepoint(1000));
+        assert_eq!(whitepoint(100_000), whitepoint(40000));
+    }
+
+    #[test]
+    fn cold_whitepoints() {
+        let (red, green, blue) = whitepoint(10000);
+        assert!(red < 1.0 && red < green);
+        assert_eq!(blue, 1.0);
+    }
+
+    #[test]
+    fn ramps() {
+        let ramps = GammaRamps::from_temperature(NEUTRAL_TEMPERATURE, 1.0, 3);
+        assert_eq!(ramps.red, vec![0, 32767, 65535]);
+        assert_eq!(ramps.green, ramps.red);
+        assert_eq!(ramps.blue, ramps.red);
+        assert!(ramps.validate(3).is_ok());
+        assert!(ramps.validate(256).is_err());
+
+        let dimmed = GammaRamps::from_temperature(NEUTRAL_TEMPERATURE, 0.5, 2);
+        assert_eq!(dimmed.red, vec![0, 32767]);
+
+        let warm = GammaRamps::from_temperature(2000, 1.0, 256);
+        assert_eq!(warm.red.len(), 256);
+        assert_eq!(warm.red[255], 65535);
+        assert!(warm.blue[255] < warm.green[255]);
+        assert!(warm.blue.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -1,7 +1,13 @@
+mod config;
+mod gamma;
 mod output;
 mod output_layout_manager;
 mod output_manager;
+mod profile;
 
+pub use self::config::*;
+pub use self::gamma::*;
 pub use self::output::*;
 pub use self::output_layout_manager::*;
 pub use self::output_manager::*;
+pub use self::profile::*;
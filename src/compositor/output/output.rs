@@ -1,119 +1,303 @@
 use cairo::ImageSurface;
 use cairo_sys;
 use glib::translate::ToGlibPtr;
+use wlroots::layer_shell::Layer;
 use wlroots::utils::current_time;
-use wlroots::{project_box, Area, CompositorHandle, Origin, OutputHandle, OutputHandler,
-              OutputLayoutHandle, Renderer, Size, SurfaceHandle, WL_SHM_FORMAT_ARGB8888,
-              GenericRenderer};
+use wlroots::{project_box, Area, CompositorHandle, GenericRenderer, Origin, OutputHandle,
+              OutputHandler, OutputTransform, Renderer, Size, SurfaceHandle, Texture,
+              WL_SHM_FORMAT_ARGB8888};
 
 use awesome::{Drawin, Objectable, DRAWINS_HANDLE, LUA};
-use compositor::{Server, View};
-use rlua::{self, AnyUserData, Lua, ToLua};
+use compositor::{self, DragIcon, LayerSurface, Server, View};
+use rlua::{self, AnyUserData, Lua};
+use std::cell::RefCell;
 use std::rc::Rc;
 
+thread_local! {
+    /// Textures that are no longer used, and are freed before the next frame.
+    static DEAD_TEXTURES: RefCell<Vec<Texture<'static>>> = RefCell::new(Vec::new());
+}
+
 pub struct Output;
 
 impl OutputHandler for Output {
-fn on_frame(&mut self, compositor: CompositorHandle, output: OutputHandle) {
-    dehandle!(
-        @compositor = {compositor};
-        @output = {output};
-        let state: &mut Server = compositor.data.downcast_mut().unwrap();
-        let Server { ref mut layout,
-                        ref mut views,
-                        .. } = *state;
-        let renderer = compositor.renderer.as_mut().expect("gles2 disabled");
-        let mut renderer = renderer.render(output, None);
-            renderer.clear([0.25, 0.25, 0.25, 1.0]);
-            render_views(&mut renderer, layout, views);
+    fn on_frame(&mut self, compositor: CompositorHandle, output: OutputHandle) {
+        dehandle!(
+            @compositor = {compositor};
+            @output = {output};
+            let server: &mut Server = compositor.data.downcast_mut().unwrap();
+            let output_box = with_handles!([(layout: {&mut server.layout})] => {
+                layout.get_box(Some(output))
+            }).unwrap();
+            let (_, buffer_age) = output.make_current();
+            // Nothing changed, so there's no need to render anything.
+            let damaged = match server.damage.take(&output.weak_reference(),
+                                                   output_box,
+                                                   buffer_age) {
+                Some(damaged) => damaged,
+                None => {
+                    send_frames_done(server, &output.weak_reference(), output_box);
+                    return
+                }
+            };
+            let renderer = compositor.renderer.as_mut().expect("gles2 disabled");
+            drop_dead_textures(renderer);
+            let mut renderer = renderer.render(output, None);
+            let views_captured =
+                compositor::render_view_captures(&mut renderer, &mut server.captures, output_box);
+            let damaged = if views_captured { output_box } else { damaged };
             LUA.with(|lua| {
                 let lua = lua.borrow();
-                match render_drawins(&*lua, &mut renderer) {
-                    Ok(_) => {},
-                    Err(err) => {
-                        warn!("Error rendering drawins: {:#?}", err);
-                    }
-                }
-            }));
+                render_output(&*lua, &mut renderer, server, RenderArea { output_box, damaged })
+            });
+            // The frame is shown once the renderer is dropped, so this has
+            // to happen first.
+            compositor::read_captures(&mut renderer, &mut server.captures, output_box);
+        );
+    }
+
+    fn destroyed(&mut self, compositor: CompositorHandle, output: OutputHandle) {
+        with_handles!([(compositor: {compositor.clone()})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut outputs,
+                         ref mut damage,
+                         ref mut gamma_controlled,
+                         ref mut captures,
+                         ref layer_surfaces,
+                         .. } = *server;
+            outputs.retain(|other| *other != output);
+            gamma_controlled.retain(|other| *other != output);
+            damage.remove_output(&output);
+            captures.remove_output(&output);
+            // Layer surfaces can't move between outputs, the client has to
+            // make a new one to be shown somewhere else.
+            for layer_surface in layer_surfaces.iter().filter(|l| l.output == output) {
+                layer_surface.handle.run(|layer_surface| layer_surface.close()).ok();
+            }
+        }).unwrap();
+        compositor::apply_output_profile(compositor.clone());
+        compositor::outputs_changed(compositor);
+    }
+}
+
+/// Tells every surface shown on the output that its frame is done, when
+/// nothing had to be rendered.
+fn send_frames_done(server: &Server, output: &OutputHandle, output_box: Area) {
+    let Server { ref views,
+                 ref unmanaged,
+                 ref layer_surfaces,
+                 ref seat,
+                 .. } = *server;
+    let done = |mut surface: SurfaceHandle, lx: i32, ly: i32| {
+        with_handles!([(surface: {&mut surface})] => {
+            let (width, height) = surface.current_state().size();
+            let surface_box = Area::new(Origin::new(lx, ly), Size::new(width, height));
+            if compositor::intersection(output_box, surface_box).is_some() {
+                surface.send_frame_done(current_time());
+            }
+        }).ok();
+    };
+    for view in views.iter().chain(unmanaged.iter()) {
+        let Origin { x, y } = view.origin.get();
+        view.for_each_surface(&mut |surface, sx, sy| done(surface, x + sx, y + sy));
+    }
+    let on_output = layer_surfaces.iter().filter(|layer_surface| {
+                                                     layer_surface.mapped.get()
+                                                     && layer_surface.output == *output
+                                                 });
+    for layer_surface in on_output {
+        let Origin { x, y } = layer_surface.geometry.get().origin;
+        layer_surface.for_each_surface(&mut |surface, sx, sy| done(surface, x + sx, y + sy));
+    }
+    for drag_icon in seat.drag_icons.iter().filter(|drag_icon| drag_icon.mapped) {
+        let Origin { x, y } = drag_icon.area.origin;
+        drag_icon.for_each_surface(&mut |surface, sx, sy| done(surface, x + sx, y + sy));
+    }
+}
+
+/// What part of the layout is being rendered.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderArea {
+    /// Where the output is in the layout.
+    pub output_box: Area,
+    /// What needs to be repainted, in output layout coordinates.
+    pub damaged: Area
+}
+
+/// Frees the textures that are no longer used.
+pub fn drop_dead_textures(renderer: &mut GenericRenderer) {
+    DEAD_TEXTURES.with(|textures| {
+                           for texture in textures.borrow_mut().drain(..) {
+                               renderer.drop_texture(texture);
+                           }
+                       });
+}
+
+/// Renders everything in the damaged part of the output being rendered.
+pub fn render_output(lua: &Lua, renderer: &mut Renderer, server: &mut Server, area: RenderArea) {
+    let Server { ref mut views,
+                 ref mut unmanaged,
+                 ref layer_surfaces,
+                 ref mut wallpaper,
+                 ref seat,
+                 .. } = *server;
+    let scissor = scissor_coords(area.damaged,
+                                 area.output_box,
+                                 renderer.output.scale(),
+                                 renderer.output.get_transform());
+    renderer.render_scissor(Some(scissor));
+    wallpaper.render(renderer, area.output_box);
+    render_layer(renderer, area, layer_surfaces, Layer::Background);
+    render_layer(renderer, area, layer_surfaces, Layer::Bottom);
+    render_views(renderer, area, views);
+    if let Err(err) = render_drawins(lua, renderer, area) {
+        warn!("Error rendering drawins: {:#?}", err);
     }
+    // Unmanaged windows (e.g menus) are above the rest of the views.
+    render_views(renderer, area, unmanaged);
+    render_layer(renderer, area, layer_surfaces, Layer::Top);
+    render_layer(renderer, area, layer_surfaces, Layer::Overlay);
+    // What is being dragged is above everything else.
+    render_drag_icons(renderer, area, &seat.drag_icons);
+    renderer.render_scissor(None);
+}
+
+/// Converts an area from output layout coordinates into the coordinates of
+/// the output, scaled but not rotated, e.g to project a texture onto it.
+///
+/// The edges are rounded rather than the size, so that areas next to each
+/// other don't get gaps between them at fractional scales.
+pub fn output_coords(area: Area, output_box: Area, scale: f32) -> Area {
+    let Origin { x, y } = area.origin;
+    let Size { width, height } = area.size;
+    let (x, y) = ((x - output_box.origin.x) as f32, (y - output_box.origin.y) as f32);
+    let (x1, y1) = ((x * scale).round() as i32, (y * scale).round() as i32);
+    let x2 = ((x + width as f32) * scale).round() as i32;
+    let y2 = ((y + height as f32) * scale).round() as i32;
+    Area::new(Origin::new(x1, y1), Size::new(x2 - x1, y2 - y1))
+}
+
+/// Converts an area from output layout coordinates into the buffer of the
+/// output, which is rotated if the output is, for the renderer to scissor
+/// in.
+pub fn scissor_coords(area: Area,
+                      output_box: Area,
+                      scale: f32,
+                      transform: OutputTransform)
+                      -> Area {
+    let area = output_coords(area, output_box, scale);
+    let width = (output_box.size.width as f32 * scale).round() as i32;
+    let height = (output_box.size.height as f32 * scale).round() as i32;
+    transform_area(area, transform.invert(), width, height)
 }
 
+/// Applies a transform to an area within a box of the given size, like
+/// `wlr_box_transform`.
+pub fn transform_area(area: Area, transform: OutputTransform, width: i32, height: i32) -> Area {
+    use wlroots::OutputTransform::*;
+    let Origin { x, y } = area.origin;
+    let Size { width: w, height: h } = area.size;
+    let (x, y, w, h) = match transform {
+        WL_OUTPUT_TRANSFORM_NORMAL => (x, y, w, h),
+        WL_OUTPUT_TRANSFORM_90 => (height - y - h, x, h, w),
+        WL_OUTPUT_TRANSFORM_180 => (width - x - w, height - y - h, w, h),
+        WL_OUTPUT_TRANSFORM_270 => (y, width - x - w, h, w),
+        WL_OUTPUT_TRANSFORM_FLIPPED => (width - x - w, y, w, h),
+        WL_OUTPUT_TRANSFORM_FLIPPED_90 => (y, x, h, w),
+        WL_OUTPUT_TRANSFORM_FLIPPED_180 => (x, height - y - h, w, h),
+        WL_OUTPUT_TRANSFORM_FLIPPED_270 => (height - y - h, width - x - w, h, w)
+    };
+    Area::new(Origin::new(x, y), Size::new(w, h))
+}
+
+/// Render a surface, if it is in the damaged part of the output.
+///
+/// Every surface on the output is told that its frame is done, even if it
+/// wasn't in the damage, because its client waits for that before drawing
+/// again. Surfaces that can't be seen aren't told, so that their clients
+/// don't keep drawing.
 fn render_surface(renderer: &mut Renderer,
-                  layout: &mut OutputLayoutHandle,
+                  area: RenderArea,
                   surface: &mut SurfaceHandle,
                   lx: i32,
                   ly: i32) {
-    with_handles!([(surface: {surface}), (layout: {&mut *layout})] => {
+    with_handles!([(surface: {surface})] => {
         let (width, height) = surface.current_state().size();
-        let (render_width, render_height) =
-            (width * renderer.output.scale() as i32,
-            height * renderer.output.scale() as i32);
-        let render_box = Area::new(Origin::new(lx, ly),
-        Size::new(render_width,
-                  render_height));
-
-        if layout.intersects(renderer.output, render_box) {
-            let transform = renderer.output.get_transform().invert();
-            let matrix = project_box(render_box,
-                                     transform,
-                                     0.0,
-                                     renderer.output
-                                     .transform_matrix());
-            renderer.render_texture_with_matrix(&surface.texture(), matrix);
-            surface.send_frame_done(current_time());
+        let surface_box = Area::new(Origin::new(lx, ly), Size::new(width, height));
+        if compositor::intersection(area.output_box, surface_box).is_none() {
+            return
         }
-
+        surface.send_frame_done(current_time());
+        if compositor::intersection(area.damaged, surface_box).is_none() {
+            return
+        }
+        let scale = renderer.output.scale();
+        let render_box = output_coords(surface_box, area.output_box, scale);
+        let transform = renderer.output.get_transform().invert();
+        let matrix = project_box(render_box,
+                                 transform,
+                                 0.0,
+                                 renderer.output
+                                 .transform_matrix());
+        renderer.render_texture_with_matrix(&surface.texture(), matrix);
     }).unwrap();
 }
 
 /// Render all of the client views.
-fn render_views(renderer: &mut Renderer,
-                layout: &mut OutputLayoutHandle,
-                views: &mut Vec<Rc<View>>) {
+fn render_views(renderer: &mut Renderer, area: RenderArea, views: &mut Vec<Rc<View>>) {
     for view in views.iter_mut().rev() {
-        let origin = view.origin.get();
-        view.for_each_surface(&mut |surface: SurfaceHandle, sx, sy| {
-            dehandle!(
-                @surface = {surface};
-                @layout = {&*layout};
-                let (width, height) = surface.current_state().size();
-                let (render_width, render_height) =
-                    (width * renderer.output.scale() as i32,
-                     height * renderer.output.scale() as i32);
-                let render_box = Area::new(Origin::new(origin.x + sx, origin.y + sy),
-                                           Size::new(render_width,
-                                                     render_height));
-
-                if layout.intersects(renderer.output, render_box) {
-                    let transform = renderer.output.get_transform().invert();
-                    let matrix = project_box(render_box,
-                                             transform,
-                                             0.0,
-                                             renderer.output
-                                             .transform_matrix());
-                    renderer.render_texture_with_matrix(&surface.texture(), matrix);
-                    surface.send_frame_done(current_time());
-                })
-        });
+        render_view(renderer, area, view);
     }
 }
 
-fn remove_old_drawins(lua: &Lua, renderer: &GenericRenderer) -> rlua::Result<()> {
-    let mut drawins = lua.named_registry_value::<Vec<AnyUserData>>(DRAWINS_HANDLE)?;
-    for drawin_obj in &mut drawins {
-        let mut drawin = Drawin::cast(drawin_obj.clone().into())?;
-        drawin.texture()?.take().map(|texture| renderer.drop_texture(texture));
-        *drawin_obj = match drawin.to_lua(lua)? {
-            rlua::Value::UserData(userdata) => userdata,
-            _ => unreachable!()
-        }
+/// Render a view, including its popups and subsurfaces.
+pub fn render_view(renderer: &mut Renderer, area: RenderArea, view: &View) {
+    let origin = view.origin.get();
+    view.for_each_surface(&mut |mut surface: SurfaceHandle, sx, sy| {
+                              render_surface(renderer,
+                                             area,
+                                             &mut surface,
+                                             origin.x + sx,
+                                             origin.y + sy)
+                          });
+}
+
+/// Render the layer surfaces on one layer of the output being rendered.
+fn render_layer(renderer: &mut Renderer,
+                area: RenderArea,
+                layer_surfaces: &[Rc<LayerSurface>],
+                layer: Layer) {
+    let output = renderer.output.weak_reference();
+    let on_layer = layer_surfaces.iter().filter(|layer_surface| {
+                                                    layer_surface.mapped.get()
+                                                    && layer_surface.layer.get() == layer
+                                                    && layer_surface.output == output
+                                                });
+    for layer_surface in on_layer {
+        let Origin { x, y } = layer_surface.geometry.get().origin;
+        layer_surface.for_each_surface(&mut |mut surface: SurfaceHandle, sx, sy| {
+                                           render_surface(renderer,
+                                                          area,
+                                                          &mut surface,
+                                                          x + sx,
+                                                          y + sy)
+                                       });
     }
-    lua.set_named_registry_value(DRAWINS_HANDLE, drawins.to_lua(lua)?)?;
-    Ok(())
+}
+
+/// Frees a texture the next time an output is rendered.
+///
+/// Textures can only be freed with the renderer, which is not available
+/// when e.g Lua garbage collects a drawable.
+pub fn free_texture(texture: Texture<'static>) {
+    // When shutting down this can be called after the list is gone, in which
+    // case it doesn't matter that the texture is leaked.
+    DEAD_TEXTURES.try_with(|textures| textures.borrow_mut().push(texture))
+                 .ok();
 }
 
 /// Render all of the drawins provided by Lua.
-fn render_drawins(lua: &Lua, renderer: &mut Renderer) -> rlua::Result<()> {
+fn render_drawins(lua: &Lua, renderer: &mut Renderer, area: RenderArea) -> rlua::Result<()> {
     let drawins = lua.named_registry_value::<Vec<AnyUserData>>(DRAWINS_HANDLE)?;
     for drawin in drawins {
         let mut drawin = Drawin::cast(drawin.into())?;
@@ -121,35 +305,36 @@ fn render_drawins(lua: &Lua, renderer: &mut Renderer) -> rlua::Result<()> {
             continue
         }
         let geometry = drawin.get_geometry()?;
-        let texture;
-        {
-            let mut drawable = drawin.drawable()?;
-            let mut drawable_state = drawable.get_object_mut()?;
-            let surface = match drawable_state.surface.as_mut() {
-                Some(surface) => surface,
-                None => continue
-            };
-            let Area { size: Size { width, height },
-                       .. } = geometry;
+        if compositor::intersection(area.damaged, geometry).is_none() {
+            continue
+        }
+        let mut drawable = drawin.drawable()?;
+        let texture = drawable.texture(|surface| {
+            // The surface is bigger than the geometry on scaled screens.
+            let (width, height) = (surface.get_width(), surface.get_height());
+            let stride = surface.get_stride();
             let data = get_data(surface);
-            texture = renderer.create_texture_from_pixels(WL_SHM_FORMAT_ARGB8888,
-                                                          (width * 4) as _,
-                                                          width as _,
-                                                          height as _,
-                                                          data)
-                .expect("Could not allocate texture");
+            let texture = renderer.create_texture_from_pixels(WL_SHM_FORMAT_ARGB8888,
+                                                              stride as _,
+                                                              width as _,
+                                                              height as _,
+                                                              data)
+                                  .expect("Could not allocate texture");
+            Some(texture)
+        })?;
+        if let Some(ref texture) = *texture {
             let transform_matrix = renderer.output.transform_matrix();
             let inverted_transform = renderer.output.get_transform().invert();
-            let matrix = project_box(geometry, inverted_transform, 0.0, transform_matrix);
-            renderer.render_texture_with_matrix(&texture, matrix);
+            let render_box = output_coords(geometry, area.output_box, renderer.output.scale());
+            let matrix = project_box(render_box, inverted_transform, 0.0, transform_matrix);
+            renderer.render_texture_with_matrix(texture, matrix);
         }
-        *drawin.texture()? = Some(texture);
     }
     Ok(())
 }
 
 /// Get the data associated with an ImageSurface.
-fn get_data(surface: &mut ImageSurface) -> &[u8] {
+pub fn get_data(surface: &mut ImageSurface) -> &[u8] {
     use std::slice;
     // NOTE This is safe to do because there's one thread.
     //
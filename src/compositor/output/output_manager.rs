@@ -1,5 +1,7 @@
-use compositor::{Output, Server};
-use wlroots::{CompositorHandle, OutputBuilder, OutputBuilderResult, OutputManagerHandler};
+use awesome::{self, LUA};
+use compositor::{self, Output, Server};
+use wlroots::{self, Area, CompositorHandle, OutputBuilder, OutputBuilderResult, OutputHandle,
+              OutputLayoutHandle, OutputManagerHandler, Size};
 
 pub struct OutputManager;
 
@@ -14,12 +16,17 @@ impl OutputManagerHandler for OutputManager {
                              compositor: CompositorHandle,
                              builder: OutputBuilder<'output>)
                              -> Option<OutputBuilderResult<'output>> {
-        dehandle!(
-            @compositor = {compositor};
+        let res = dehandle!(
+            @compositor = {compositor.clone()};
             let server: &mut Server = compositor.into();
             let res = builder.build_best_mode(Output);
             server.outputs.push(res.output.clone());
-            let Server { ref mut cursor,
+            server.damage.add_output(res.output.clone());
+            let Server { ref backend,
+                         ref output_configs,
+                         ref gamma,
+                         ref seat,
+                         ref mut cursor,
                          ref mut layout,
                          ref mut xcursor_manager,
                          .. } = *server;
@@ -27,14 +34,119 @@ impl OutputManagerHandler for OutputManager {
             @cursor = {cursor};
             {
                 @output = {&res.output};
-                layout.add_auto(output);
+                // Virtual outputs have no modes, so they are sized by hand.
+                if let Some(Size { width, height }) = backend.output_size() {
+                    output.set_custom_mode(width, height, 0);
+                };
+                match output_configs.get(&output.name()) {
+                    Some(config) => {
+                        if let Err(err) = config.apply(layout, output) {
+                            warn!("Could not configure {}: {}", output.name(), err);
+                            layout.add_auto(output)
+                        }
+                    }
+                    None => layout.add_auto(output)
+                };
+                if let Some(ramps) = gamma.get(&output.name()) {
+                    ramps.apply(output);
+                }
                 cursor.attach_output_layout(layout);
                 xcursor_manager.load(output.scale());
-                xcursor_manager.set_cursor_image("left_ptr".to_string(), cursor);
+                xcursor_manager.set_cursor_image(seat.default_cursor(), cursor);
                 let (x, y) = cursor.coords();
                 cursor.warp(None, x, y)
             }
             Some(res)
-        )
+        );
+        compositor::apply_output_profile(compositor.clone());
+        outputs_changed(compositor);
+        res
     }
 }
+
+/// Where each of the enabled outputs is in the layout.
+pub fn output_boxes(layout: &mut OutputLayoutHandle,
+                    outputs: &[OutputHandle],
+                    powered_off: &[OutputHandle])
+                    -> Vec<(OutputHandle, Area)> {
+    with_handles!([(layout: {layout})] => {
+        outputs.iter()
+               .filter_map(|output| {
+                               let powered_off = powered_off.contains(output);
+                               let output_box = output.run(|output| {
+                                   if output.enabled() || powered_off {
+                                       Some(layout.get_box(Some(output)))
+                                   } else {
+                                       None
+                                   }
+                               });
+                               output_box.ok()
+                                         .and_then(|output_box| output_box)
+                                         .map(|output_box| (output.clone(), output_box))
+                           })
+               .collect()
+    }).unwrap()
+}
+
+/// Lets Lua and the layer surfaces know that the outputs changed, e.g
+/// because one was plugged in.
+///
+/// This must not be called while the compositor or Lua is borrowed.
+pub fn outputs_changed(compositor: CompositorHandle) {
+    let outputs = with_handles!([(compositor: {compositor})] => {
+        compositor::rearrange_outputs(compositor)
+    }).unwrap();
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 if let Err(err) = awesome::update_screens(&*lua, &outputs) {
+                     warn!("Could not update the screens: {:?}", err);
+                 }
+             });
+}
+
+/// How many pixels there are per millimetre if an output doesn't know its
+/// physical size, e.g because it is virtual.
+const FALLBACK_PIXELS_PER_MM: f64 = 96.0 / 25.4;
+
+/// The size of the whole output layout in pixels, and in millimetres, from
+/// outside of a compositor callback (e.g from Lua).
+pub fn root_size() -> (Size, Size) {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref mut layout,
+                     ref outputs,
+                     ref idle,
+                     .. } = *server;
+        let root_box = with_handles!([(layout: {&mut *layout})] => {
+            layout.get_box(None)
+        }).unwrap();
+        // The pixel density of the outputs that know their physical size.
+        let (mut pixels, mut millimetres) = ((0, 0), (0, 0));
+        for (output, output_box) in output_boxes(layout, outputs, idle.powered_off()) {
+            let (width_mm, height_mm) = match output.run(|output| output.physical_size()) {
+                Ok(physical_size) => physical_size,
+                Err(_) => continue
+            };
+            if width_mm <= 0 || height_mm <= 0 {
+                continue
+            }
+            pixels.0 += output_box.size.width;
+            pixels.1 += output_box.size.height;
+            millimetres.0 += width_mm;
+            millimetres.1 += height_mm;
+        }
+        let to_mm = |pixels_on_root: i32, pixels: i32, millimetres: i32| {
+            let pixels_per_mm = if pixels > 0 && millimetres > 0 {
+                pixels as f64 / millimetres as f64
+            } else {
+                FALLBACK_PIXELS_PER_MM
+            };
+            (pixels_on_root as f64 / pixels_per_mm).round() as i32
+        };
+        let Size { width, height } = root_box.size;
+        let size_mm = Size::new(to_mm(width, pixels.0, millimetres.0),
+                                to_mm(height, pixels.1, millimetres.1));
+        (root_box.size, size_mm)
+    )
+}
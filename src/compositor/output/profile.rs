@@ -0,0 +1,463 @@
+//! Output profiles remember how the outputs were arranged for a set of
+//! connected monitors (e.g at a desk), so that they can be arranged the same
+//! way the next time those monitors are connected.
+//!
+//! Profiles are saved to a plain text file in the config directory:
+//!
+//! ```text
+//! profile docked
+//! 	eDP-1	Sharp	0x1449	unknown	off	-	-	-	-
+//! 	DP-1	Dell Inc.	U2415	ABC123	on	1920x1200@59950	1	normal	0,0
+//! ```
+//!
+//! Each output line has tab separated fields: the name, make, model and
+//! serial number of the output, whether it is enabled, its mode, scale,
+//! transform and position. Fields that are `-` are left up to the compositor.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use wlroots::{self, Area, CompositorHandle, Origin, OutputHandle};
+
+use awesome::{self, LUA};
+use compositor::{self, ModeConfig, OutputConfig, Server};
+
+const PROFILES_FILE: &str = "output-profiles";
+
+/// What an output is connected to, used to recognize a set of monitors.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct OutputIdentity {
+    pub name: String,
+    pub make: String,
+    pub model: String,
+    pub serial: String
+}
+
+impl OutputIdentity {
+    pub fn new(output: &wlroots::Output) -> Self {
+        OutputIdentity { name: output.name(),
+                         make: output.make(),
+                         model: output.model(),
+                         serial: output.serial() }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutputProfile {
+    pub name: String,
+    pub outputs: Vec<(OutputIdentity, OutputConfig)>
+}
+
+impl OutputProfile {
+    /// Whether the profile is for exactly the given outputs.
+    pub fn matches(&self, connected: &[OutputIdentity]) -> bool {
+        self.outputs.len() == connected.len()
+        && connected.iter()
+                    .all(|identity| self.outputs.iter().any(|&(ref other, _)| other == identity))
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OutputProfiles {
+    /// Where the profiles are saved, if there is a config directory.
+    path: Option<PathBuf>,
+    pub profiles: Vec<OutputProfile>
+}
+
+impl OutputProfiles {
+    /// Loads the saved profiles, if there are any.
+    pub fn load() -> Self {
+        let path = profiles_path();
+        let mut contents = String::new();
+        let read = path.as_ref()
+                       .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config dir"))
+                       .and_then(File::open)
+                       .and_then(|mut file| file.read_to_string(&mut contents));
+        let profiles = match read {
+            Ok(_) => match parse_profiles(&contents) {
+                Ok(profiles) => profiles,
+                Err(err) => {
+                    warn!("Could not parse output profiles: {}", err);
+                    Vec::new()
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                warn!("Could not read output profiles: {}", err);
+                Vec::new()
+            }
+        };
+        OutputProfiles { path, profiles }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = self.path
+                       .as_ref()
+                       .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No config dir"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        File::create(path)?.write_all(serialize_profiles(&self.profiles).as_bytes())
+    }
+
+    pub fn find(&self, name: &str) -> Option<&OutputProfile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    /// The first profile for exactly the given outputs.
+    pub fn matching(&self, connected: &[OutputIdentity]) -> Option<&OutputProfile> {
+        self.profiles.iter().find(|profile| profile.matches(connected))
+    }
+
+    /// Adds a profile, replacing the one with the same name.
+    pub fn insert(&mut self, profile: OutputProfile) {
+        match self.profiles.iter().position(|other| other.name == profile.name) {
+            Some(index) => self.profiles[index] = profile,
+            None => self.profiles.push(profile)
+        }
+    }
+}
+
+fn profiles_path() -> Option<PathBuf> {
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".config")
+    };
+    Some(config_dir.join("way-cooler").join(PROFILES_FILE))
+}
+
+fn parse_profiles(contents: &str) -> Result<Vec<OutputProfile>, String> {
+    let mut profiles: Vec<OutputProfile> = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line_number = line_number + 1;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue
+        }
+        if line.starts_with("profile ") {
+            let name = line["profile ".len()..].trim().to_string();
+            profiles.push(OutputProfile { name,
+                                          outputs: Vec::new() });
+            continue
+        }
+        let profile = match profiles.last_mut() {
+            Some(profile) if line.starts_with('\t') => profile,
+            _ => return Err(format!("line {}: expected a profile", line_number))
+        };
+        let output =
+            parse_output(&line[1..]).map_err(|err| format!("line {}: {}", line_number, err))?;
+        profile.outputs.push(output);
+    }
+    Ok(profiles)
+}
+
+fn parse_output(line: &str) -> Result<(OutputIdentity, OutputConfig), String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 9 {
+        return Err(format!("expected 9 fields, got {}", fields.len()))
+    }
+    let identity = OutputIdentity { name: fields[0].into(),
+                                    make: fields[1].into(),
+                                    model: fields[2].into(),
+                                    serial: fields[3].into() };
+    let enabled = match fields[4] {
+        "on" => Some(true),
+        "off" => Some(false),
+        "-" => None,
+        enabled => return Err(format!("invalid enabled state \"{}\"", enabled))
+    };
+    let mode = optional(fields[5], |mode| {
+        let (size, refresh) = match mode.find('@') {
+            Some(index) => (&mode[..index], Some(&mode[index + 1..])),
+            None => (mode, None)
+        };
+        let mut size = size.splitn(2, 'x');
+        let (width, height) = match (size.next(), size.next()) {
+            (Some(width), Some(height)) => (parse_number(width)?, parse_number(height)?),
+            _ => return Err(format!("invalid mode \"{}\"", mode))
+        };
+        let refresh = match refresh {
+            Some(refresh) => Some(parse_number(refresh)?),
+            None => None
+        };
+        Ok(ModeConfig { width,
+                        height,
+                        refresh })
+    })?;
+    let scale = optional(fields[6], |scale| {
+        scale.parse::<f32>()
+             .map_err(|_| format!("invalid scale \"{}\"", scale))
+    })?;
+    let transform = optional(fields[7], compositor::transform_from_name)?;
+    let position = optional(fields[8], |position| {
+        let mut coords = position.splitn(2, ',');
+        match (coords.next(), coords.next()) {
+            (Some(x), Some(y)) => Ok(Origin::new(parse_number(x)?, parse_number(y)?)),
+            _ => Err(format!("invalid position \"{}\"", position))
+        }
+    })?;
+    let config = OutputConfig { enabled,
+                                mode,
+                                scale,
+                                transform,
+                                position };
+    Ok((identity, config))
+}
+
+fn optional<T, F>(field: &str, parse: F) -> Result<Option<T>, String>
+    where F: FnOnce(&str) -> Result<T, String>
+{
+    match field {
+        "-" => Ok(None),
+        field => parse(field).map(Some)
+    }
+}
+
+fn parse_number(number: &str) -> Result<i32, String> {
+    number.parse()
+          .map_err(|_| format!("invalid number \"{}\"", number))
+}
+
+fn serialize_profiles(profiles: &[OutputProfile]) -> String {
+    let mut contents = String::from("# Output profiles saved by way-cooler\n");
+    for profile in profiles {
+        contents.push_str(&format!("profile {}\n", profile.name));
+        for &(ref identity, ref config) in &profile.outputs {
+            let enabled = match config.enabled {
+                Some(true) => "on".into(),
+                Some(false) => "off".into(),
+                None => "-".into()
+            };
+            let mode = match config.mode {
+                Some(ModeConfig { width,
+                                  height,
+                                  refresh: Some(refresh) }) => {
+                    format!("{}x{}@{}", width, height, refresh)
+                }
+                Some(ModeConfig { width, height, .. }) => format!("{}x{}", width, height),
+                None => "-".into()
+            };
+            let scale = config.scale
+                              .map_or("-".into(), |scale| scale.to_string());
+            let transform = config.transform
+                                  .map_or("-".into(), |transform| {
+                                      compositor::transform_name(transform).to_string()
+                                  });
+            let position = config.position
+                                 .map_or("-".into(), |Origin { x, y }| format!("{},{}", x, y));
+            let fields: [String; 9] = [identity.name.clone(),
+                                       identity.make.clone(),
+                                       identity.model.clone(),
+                                       identity.serial.clone(),
+                                       enabled,
+                                       mode,
+                                       scale,
+                                       transform,
+                                       position];
+            contents.push('\t');
+            contents.push_str(&fields.join("\t"));
+            contents.push('\n');
+        }
+    }
+    contents
+}
+
+/// The outputs that are connected right now.
+pub fn connected_outputs(server: &Server) -> Vec<OutputIdentity> {
+    server.outputs
+          .iter()
+          .filter_map(|output| output.run(|output| OutputIdentity::new(output)).ok())
+          .collect()
+}
+
+/// Applies the saved profile with the given name to the outputs.
+///
+/// The stored configurations (e.g from rc.lua) are left alone, so they are
+/// what is used again once the outputs are no longer set up by a profile.
+pub fn set_output_profile(server: &mut Server, name: &str) -> Result<(), String> {
+    let profile = server.output_profiles
+                        .find(name)
+                        .cloned()
+                        .ok_or_else(|| format!("No output profile named \"{}\"", name))?;
+    info!("Applying output profile \"{}\"", name);
+    for (identity, config) in profile.outputs {
+        if let Err(err) = compositor::apply_output_config(server, &identity.name, &config) {
+            warn!("Could not configure {}: {}", identity.name, err);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the profile saved for the outputs that are connected now, e.g
+/// because a monitor was just plugged in.
+///
+/// Lua can pick a different profile, or none at all.
+pub fn apply_output_profile(compositor: CompositorHandle) {
+    let (connected, matching) = with_handles!([(compositor: {compositor.clone()})] => {
+        let server: &mut Server = compositor.into();
+        let connected = connected_outputs(server);
+        let matching = server.output_profiles
+                             .matching(&connected)
+                             .map(|profile| profile.name.clone());
+        (connected, matching)
+    }).unwrap();
+    let chosen = LUA.with(|lua| {
+                              let lua = lua.borrow();
+                              let res = awesome::choose_output_profile(&*lua,
+                                                                       &connected,
+                                                                       matching.clone());
+                              match res {
+                                  Ok(chosen) => chosen,
+                                  Err(err) => {
+                                      warn!("Output profile handler failed: {:?}", err);
+                                      matching
+                                  }
+                              }
+                          });
+    with_handles!([(compositor: {compositor})] => {
+        let server: &mut Server = compositor.into();
+        match chosen {
+            Some(name) => {
+                if let Err(err) = set_output_profile(server, &name) {
+                    warn!("{}", err);
+                }
+            }
+            // Undo whatever the last profile did to the outputs.
+            None => reset_outputs(server)
+        }
+    }).unwrap();
+}
+
+/// Sets up the connected outputs the way they are without a profile, i.e
+/// with their stored configuration or else automatically.
+fn reset_outputs(server: &mut Server) {
+    let Server { ref mut layout,
+                 ref outputs,
+                 ref output_configs,
+                 ref idle,
+                 .. } = *server;
+    for output in outputs {
+        if idle.powered_off().contains(output) {
+            continue
+        }
+        with_handles!([(layout: {&mut *layout}), (output: {output})] => {
+            match output_configs.get(&output.name()) {
+                Some(config) => {
+                    if let Err(err) = config.apply(layout, output) {
+                        warn!("Could not configure {}: {}", output.name(), err);
+                        layout.add_auto(output)
+                    }
+                }
+                None => {
+                    output.enable(true);
+                    layout.add_auto(output)
+                }
+            }
+        }).ok();
+    }
+}
+
+/// Saves how the outputs are set up right now as a profile for the
+/// connected outputs, from outside of a compositor callback (e.g from Lua).
+pub fn save_output_profile(name: &str) -> io::Result<()> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref mut layout,
+                     ref outputs,
+                     ref mut output_profiles,
+                     ref idle,
+                     .. } = *server;
+        let mut profile = OutputProfile { name: name.to_string(),
+                                          outputs: Vec::new() };
+        with_handles!([(layout: {&mut *layout})] => {
+            for output in outputs {
+                let powered_off = idle.powered_off().contains(output);
+                output.run(|output| {
+                              let config = OutputConfig::current(layout, output, powered_off);
+                              profile.outputs.push((OutputIdentity::new(output), config))
+                          })
+                      .ok();
+            }
+        }).unwrap();
+        output_profiles.insert(profile);
+        output_profiles.save()
+    )
+}
+
+/// The names of the saved profiles, from outside of a compositor callback
+/// (e.g from Lua).
+pub fn output_profile_names() -> Vec<String> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.output_profiles
+              .profiles
+              .iter()
+              .map(|profile| profile.name.clone())
+              .collect()
+    )
+}
+
+/// Applies the saved profile with the given name from outside of a
+/// compositor callback (e.g from Lua).
+///
+/// Returns the rearranged outputs, for Lua to update its screens with.
+pub fn load_output_profile(name: &str) -> Result<Vec<(OutputHandle, Area, Area)>, String> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let res = {
+            let server: &mut Server = (&mut *compositor).into();
+            set_output_profile(server, name)
+        };
+        res.map(|_| compositor::rearrange_outputs(compositor))
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wlroots::OutputTransform;
+
+    fn identity(name: &str) -> OutputIdentity {
+        OutputIdentity { name: name.into(),
+                         make: "Dell Inc.".into(),
+                         model: "U2415".into(),
+                         serial: "unknown".into() }
+    }
+
+    #[test]
+    fn round_trip() {
+        let docked = OutputConfig { enabled: Some(true),
+                                    mode: Some(ModeConfig { width: 1920,
+                                                            height: 1200,
+                                                            refresh: Some(59950) }),
+                                    scale: Some(1.5),
+                                    transform: Some(OutputTransform::WL_OUTPUT_TRANSFORM_90),
+                                    position: Some(Origin::new(-1920, 0)) };
+        let off = OutputConfig { enabled: Some(false),
+                                 mode: Some(ModeConfig { width: 800,
+                                                         height: 600,
+                                                         refresh: None }),
+                                 ..OutputConfig::default() };
+        let profiles = vec![OutputProfile { name: "docked".into(),
+                                            outputs: vec![(identity("DP-1"), docked),
+                                                          (identity("eDP-1"), off)] },
+                            OutputProfile { name: "laptop".into(),
+                                            outputs: vec![(identity("eDP-1"),
+                                                           OutputConfig::default())] }];
+        let contents = serialize_profiles(&profiles);
+        assert_eq!(parse_profiles(&contents), Ok(profiles));
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        assert!(parse_profiles("\tDP-1\tDell\tU2415\t-\ton\t-\t-\t-\t-\n").is_err());
+        assert!(parse_profiles("profile a\n\tDP-1\tDell\tU2415\t-\ton\n").is_err());
+        assert!(parse_profiles("profile a\n\tDP-1\tDell\tU2415\t-\tyes\t-\t-\t-\t-\n").is_err());
+        assert!(parse_profiles("profile a\n\tDP-1\tDell\tU2415\t-\ton\t1920\t-\t-\t-\n").is_err());
+        assert!(parse_profiles("profile a\n\tDP-1\tDell\tU2415\t-\ton\t-\t-\tsideways\t-\n")
+                    .is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Copying what is shown on the outputs, both for screenshot tools (using
+//! the wlr-screencopy protocol) and for Lua (`root.content`,
+//! `screen:content` and `client:content`).
+
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::rc::Rc;
+
+use cairo::{Context, Format, ImageSurface, Matrix};
+use glib::{self, Continue};
+use wlroots::{self, Area, CompositorHandle, Origin, OutputHandle, OutputTransform, Renderer,
+              ScreencopyManagerHandler, Size, WL_SHM_FORMAT_ARGB8888};
+
+use awesome::{self, LUA};
+use compositor::{self, RenderArea, Server, View};
+
+pub struct ScreencopyManager;
+
+impl ScreencopyManagerHandler for ScreencopyManager {
+    fn capture(&mut self, compositor: CompositorHandle, output: OutputHandle) {
+        // The buffer is copied when the next frame is rendered, so there
+        // has to be one even if nothing changed.
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            server.damage.add_whole_output(&output);
+        }).unwrap();
+    }
+}
+
+/// Captures of areas of the output layout for Lua, which are read back when
+/// the outputs they cover draw their next frame.
+#[derive(Default)]
+pub struct Captures {
+    next_id: u64,
+    pending: Vec<Capture>,
+    views: Vec<ViewCapture>,
+    /// Captures that are done but haven't been handed to Lua yet, or `None`
+    /// if they couldn't be read back.
+    finished: Vec<(u64, Option<ImageSurface>)>
+}
+
+struct Capture {
+    id: u64,
+    area: Area,
+    surface: ImageSurface,
+    /// The outputs that haven't been read back yet.
+    waiting: Vec<OutputHandle>,
+    failed: bool
+}
+
+/// A capture of a view, which is rendered on its own by an output before
+/// that output renders its next frame.
+struct ViewCapture {
+    id: u64,
+    view: Rc<View>,
+    output: OutputHandle
+}
+
+impl Debug for Captures {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Captures")
+         .field("pending", &self.pending.len())
+         .field("views", &self.views.len())
+         .field("finished", &self.finished.len())
+         .finish()
+    }
+}
+
+impl Captures {
+    /// Stops waiting on an output that is gone.
+    pub fn remove_output(&mut self, output: &OutputHandle) {
+        for capture in &mut self.pending {
+            capture.waiting.retain(|other| other != output);
+        }
+        let (failed, views) = mem::replace(&mut self.views, Vec::new())
+            .into_iter()
+            .partition::<Vec<_>, _>(|capture| capture.output == *output);
+        self.views = views;
+        for capture in failed {
+            self.done(capture.id, None);
+        }
+        self.finish();
+    }
+
+    /// Moves the captures that aren't waiting on any output anymore over to
+    /// Lua.
+    fn finish(&mut self) {
+        let mut index = 0;
+        while index < self.pending.len() {
+            if !self.pending[index].waiting.is_empty() {
+                index += 1;
+                continue
+            }
+            let Capture { id, surface, failed, .. } = self.pending.remove(index);
+            surface.flush();
+            self.done(id, if failed { None } else { Some(surface) });
+        }
+    }
+
+    /// Hands a capture over to Lua, or `None` if it couldn't be made.
+    fn done(&mut self, id: u64, surface: Option<ImageSurface>) {
+        // Lua can't be called while the compositor is in use.
+        if self.finished.is_empty() {
+            glib::idle_add(|| {
+                               hand_captures_to_lua();
+                               Continue(false)
+                           });
+        }
+        self.finished.push((id, surface));
+    }
+}
+
+/// Captures an area of the output layout, from outside of a compositor
+/// callback (e.g from Lua).
+///
+/// The outputs covering the area are read back when they draw their next
+/// frame, after which `awesome::capture_done` is called with the returned
+/// id. The capture is drawn at the given scale, and its device scale is set
+/// to it.
+pub fn capture_area(area: Area, scale: f32) -> Result<u64, String> {
+    let width = (area.size.width as f32 * scale).ceil() as i32;
+    let height = (area.size.height as f32 * scale).ceil() as i32;
+    let surface = ImageSurface::create(Format::ARgb32, width.max(1), height.max(1))
+        .map_err(|err| format!("Could not allocate the capture: {:?}", err))?;
+    surface.set_device_scale(scale as f64, scale as f64);
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref mut layout,
+                     ref outputs,
+                     ref mut damage,
+                     ref mut captures,
+                     .. } = *server;
+        // Outputs that are off because the user is idle don't draw frames.
+        let waiting: Vec<_> = compositor::output_boxes(layout, outputs, &[])
+            .into_iter()
+            .filter(|&(_, output_box)| compositor::intersection(area, output_box).is_some())
+            .map(|(output, _)| output)
+            .collect();
+        if waiting.is_empty() {
+            return Err("There is no output there".into())
+        }
+        // There has to be a new frame even if nothing changed.
+        for output in &waiting {
+            damage.add_whole_output(output);
+        }
+        let id = captures.next_id;
+        captures.next_id += 1;
+        captures.pending.push(Capture { id,
+                                        area,
+                                        surface,
+                                        waiting,
+                                        failed: false });
+        Ok(id)
+    )
+}
+
+/// Captures everything in the output layout at a scale of 1, like
+/// `capture_area`.
+pub fn capture_root() -> Result<u64, String> {
+    let root_box = dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        with_handles!([(layout: {&mut server.layout})] => {
+            layout.get_box(None)
+        }).unwrap()
+    );
+    capture_area(root_box, 1.0)
+}
+
+/// Captures the contents of a view, including its popups and subsurfaces,
+/// from outside of a compositor callback (e.g from Lua).
+///
+/// The view is rendered on its own by an output it is on, before that output
+/// renders its next frame, after which `awesome::capture_done` is called
+/// with the returned id. Anything covering the view is left out. The capture
+/// has the scale of that output as its device scale.
+pub fn capture_view(view: Rc<View>) -> Result<u64, String> {
+    let view_box = view.bounding_box();
+    if view_box.size.width <= 0 || view_box.size.height <= 0 {
+        return Err("The view has no contents".into())
+    }
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref mut layout,
+                     ref outputs,
+                     ref mut damage,
+                     ref mut captures,
+                     .. } = *server;
+        // Outputs that are off because the user is idle don't draw frames.
+        let output_boxes = compositor::output_boxes(layout, outputs, &[]);
+        // Any output can render the view, but one it is on has the right
+        // scale.
+        let output = output_boxes.iter()
+                                 .find(|&&(_, output_box)| {
+                                           compositor::intersection(view_box, output_box)
+                                               .is_some()
+                                       })
+                                 .or_else(|| output_boxes.first())
+                                 .map(|&(ref output, _)| output.clone());
+        let output = match output {
+            Some(output) => output,
+            None => return Err("There is no output to render the view on".into())
+        };
+        // There has to be a new frame even if nothing changed, and all of it
+        // has to be repainted after the view was rendered on its own.
+        damage.add_whole_output(&output);
+        let id = captures.next_id;
+        captures.next_id += 1;
+        captures.views.push(ViewCapture { id, view, output });
+        Ok(id)
+    )
+}
+
+/// Renders the views that are captured on the output being rendered on
+/// their own and reads them back, before the frame is rendered over them.
+///
+/// `output_box` is where the output is in the layout. Returns whether any
+/// view was rendered, in which case all of the frame has to be repainted.
+pub fn render_view_captures(renderer: &mut Renderer,
+                            captures: &mut Captures,
+                            output_box: Area)
+                            -> bool {
+    let output = renderer.output.weak_reference();
+    if !captures.views.iter().any(|capture| capture.output == output) {
+        return false
+    }
+    let (views, rest) = mem::replace(&mut captures.views, Vec::new())
+        .into_iter()
+        .partition::<Vec<_>, _>(|capture| capture.output == output);
+    captures.views = rest;
+    for ViewCapture { id, view, .. } in views {
+        let surface = render_view_alone(renderer, &view, output_box.size);
+        if let Err(ref err) = surface {
+            warn!("Could not capture a view on {}: {}", renderer.output.name(), err);
+        }
+        captures.done(id, surface.ok());
+    }
+    true
+}
+
+/// Renders a view on its own and reads it back.
+///
+/// Views that are bigger than the output are rendered a piece at a time,
+/// each piece being as big as the output.
+fn render_view_alone(renderer: &mut Renderer,
+                     view: &View,
+                     output_size: Size)
+                     -> Result<ImageSurface, String> {
+    let view_box = view.bounding_box();
+    if output_size.width <= 0 || output_size.height <= 0 {
+        return Err("The output has no size".into())
+    }
+    let scale = renderer.output.scale() as f64;
+    let width = (view_box.size.width as f64 * scale).ceil() as i32;
+    let height = (view_box.size.height as f64 * scale).ceil() as i32;
+    let surface = ImageSurface::create(Format::ARgb32, width.max(1), height.max(1))
+        .map_err(|err| format!("Could not allocate the capture: {:?}", err))?;
+    surface.set_device_scale(scale, scale);
+    {
+        let context = Context::new(&surface);
+        let mut y = 0;
+        while y < view_box.size.height {
+            let mut x = 0;
+            while x < view_box.size.width {
+                // Renders as if the output was showing this piece of the
+                // layout.
+                let origin = Origin::new(view_box.origin.x + x, view_box.origin.y + y);
+                let piece = Area::new(origin, output_size);
+                renderer.render_scissor(None);
+                renderer.clear([0.0, 0.0, 0.0, 0.0]);
+                compositor::render_view(renderer,
+                                        RenderArea { output_box: piece,
+                                                     damaged: piece },
+                                        view);
+                let frame = read_frame(renderer)?;
+                context.set_source_surface(&frame, x as f64, y as f64);
+                context.paint();
+                x += output_size.width;
+            }
+            y += output_size.height;
+        }
+    }
+    surface.flush();
+    Ok(surface)
+}
+
+/// Reads back the frame that was just rendered for the captures waiting on
+/// the output, before it is shown.
+///
+/// `output_box` is where the output is in the layout.
+pub fn read_captures(renderer: &mut Renderer, captures: &mut Captures, output_box: Area) {
+    let output = renderer.output.weak_reference();
+    if !captures.pending
+                .iter()
+                .any(|capture| capture.waiting.contains(&output))
+    {
+        return
+    }
+    let frame = read_frame(renderer);
+    if let Err(ref err) = frame {
+        warn!("Could not capture {}: {}", renderer.output.name(), err);
+    }
+    let waiting = captures.pending
+                          .iter_mut()
+                          .filter(|capture| capture.waiting.contains(&output));
+    for capture in waiting {
+        capture.waiting.retain(|other| *other != output);
+        match frame {
+            Ok(ref frame) => {
+                // The device scale of the frame makes it the size of the
+                // output in the layout.
+                let context = Context::new(&capture.surface);
+                context.set_source_surface(frame,
+                                           (output_box.origin.x - capture.area.origin.x) as f64,
+                                           (output_box.origin.y - capture.area.origin.y) as f64);
+                context.paint();
+            }
+            Err(_) => capture.failed = true
+        }
+    }
+    captures.finish();
+}
+
+/// Reads back what was rendered on the output.
+///
+/// The returned surface is in the same orientation as the output layout,
+/// and its device scale is the scale of the output.
+fn read_frame(renderer: &mut Renderer) -> Result<ImageSurface, String> {
+    let (width, height) = renderer.output.size();
+    let mut buffer = ImageSurface::create(Format::ARgb32, width, height)
+        .map_err(|err| format!("Could not allocate the capture: {:?}", err))?;
+    let stride = buffer.get_stride();
+    let read = {
+        let mut data = buffer.get_data()
+                             .map_err(|err| format!("Could not write capture: {:?}", err))?;
+        renderer.read_pixels(WL_SHM_FORMAT_ARGB8888,
+                             stride as _,
+                             width as _,
+                             height as _,
+                             0,
+                             0,
+                             0,
+                             0,
+                             &mut data)
+    };
+    if !read {
+        return Err("Could not read the pixels".into())
+    }
+    buffer.mark_dirty();
+    let surface = untransform(&buffer, renderer.output.get_transform())?;
+    let scale = renderer.output.scale() as f64;
+    surface.set_device_scale(scale, scale);
+    Ok(surface)
+}
+
+/// Hands the finished captures to Lua.
+fn hand_captures_to_lua() {
+    let finished = dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        mem::replace(&mut server.captures.finished, Vec::new())
+    );
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 for (id, surface) in finished {
+                     if let Err(err) = awesome::capture_done(&*lua, id, surface.as_ref()) {
+                         warn!("Could not hand a capture to Lua: {:?}", err);
+                     }
+                 }
+             });
+}
+
+/// Turns the buffer of an output right side up, undoing its transform.
+fn untransform(buffer: &ImageSurface, transform: OutputTransform) -> Result<ImageSurface, String> {
+    use wlroots::OutputTransform::*;
+    let (buffer_width, buffer_height) = (buffer.get_width(), buffer.get_height());
+    // How a point on the output, relative to its centre, ends up in the buffer.
+    let (xx, xy, yx, yy) = match transform {
+        WL_OUTPUT_TRANSFORM_NORMAL => (1.0, 0.0, 0.0, 1.0),
+        WL_OUTPUT_TRANSFORM_90 => (0.0, 1.0, -1.0, 0.0),
+        WL_OUTPUT_TRANSFORM_180 => (-1.0, 0.0, 0.0, -1.0),
+        WL_OUTPUT_TRANSFORM_270 => (0.0, -1.0, 1.0, 0.0),
+        WL_OUTPUT_TRANSFORM_FLIPPED => (-1.0, 0.0, 0.0, 1.0),
+        WL_OUTPUT_TRANSFORM_FLIPPED_90 => (0.0, 1.0, 1.0, 0.0),
+        WL_OUTPUT_TRANSFORM_FLIPPED_180 => (1.0, 0.0, 0.0, -1.0),
+        WL_OUTPUT_TRANSFORM_FLIPPED_270 => (0.0, -1.0, -1.0, 0.0)
+    };
+    // Rotating by 90 degrees swaps the width and height.
+    let (width, height) = if xx == 0.0 {
+        (buffer_height, buffer_width)
+    } else {
+        (buffer_width, buffer_height)
+    };
+    let surface = ImageSurface::create(Format::ARgb32, width, height)
+        .map_err(|err| format!("Could not allocate the capture: {:?}", err))?;
+    {
+        let (half_width, half_height) = (width as f64 / 2.0, height as f64 / 2.0);
+        let mut buffer_to_output =
+            Matrix::new(xx,
+                        yx,
+                        xy,
+                        yy,
+                        buffer_width as f64 / 2.0 - xx * half_width - xy * half_height,
+                        buffer_height as f64 / 2.0 - yx * half_width - yy * half_height);
+        buffer_to_output.invert();
+        let context = Context::new(&surface);
+        context.transform(buffer_to_output);
+        context.set_source_surface(buffer, 0.0, 0.0);
+        context.paint();
+    }
+    surface.flush();
+    Ok(surface)
+}
@@ -1,13 +1,17 @@
-use compositor::{Server, Shell, View};
-use std::collections::HashSet;
+use compositor::{self, Damage, LayerSurface, Server, Shell, View};
 use std::rc::Rc;
 use std::time::Duration;
 use wlroots;
 use wlroots::events::seat_events::SetCursorEvent;
-use wlroots::pointer_events::ButtonEvent;
+use wlroots::layer_shell::Layer;
+use wlroots::pointer_events::{AxisEvent, ButtonEvent};
 use wlroots::utils::{current_time, Edges};
-use wlroots::{Area, CompositorHandle, Cursor, CursorHandle, DragIconHandle, Origin, SeatHandle,
-              SeatHandler, Size, SurfaceHandle, SurfaceHandler, XCursorManager};
+use wlroots::{Area, CompositorHandle, Cursor, CursorHandle, DragIconHandle, KeyboardModifier,
+              Origin, PointerGrab, SeatHandle, SeatHandler, Size, SurfaceHandle, SurfaceHandler,
+              XCursorManager};
+
+/// The cursor shown when Lua didn't choose another one.
+pub const DEFAULT_CURSOR: &str = "left_ptr";
 
 #[derive(Debug, Default)]
 pub struct SeatManager;
@@ -26,19 +30,57 @@ pub enum Action {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// The icon of what is being dragged, which follows the pointer.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct DragIcon {
-    pub handle: DragIconHandle
+    pub handle: DragIconHandle,
+    /// Where the icon is drawn, in output layout coordinates.
+    pub area: Area,
+    pub mapped: bool
+}
+
+impl DragIcon {
+    pub fn surface(&self) -> SurfaceHandle {
+        with_handles!([(drag_icon: {&self.handle})] => {
+            drag_icon.surface()
+        }).unwrap()
+    }
+
+    pub fn for_each_surface(&self, f: &mut FnMut(SurfaceHandle, i32, i32)) {
+        with_handles!([(surface: {self.surface()})] => {
+            surface.for_each_surface(f);
+        }).unwrap();
+    }
+
+    /// Moves the icon to the pointer, offset by as much as the client moved
+    /// its surface.
+    fn follow(&mut self, cursor: &Cursor) {
+        let (lx, ly) = cursor.coords();
+        let (sx, sy) = with_handles!([(drag_icon: {&self.handle})] => {
+            drag_icon.offset()
+        }).unwrap_or((0, 0));
+        let size = with_handles!([(surface: {self.surface()})] => {
+            let (width, height) = surface.current_state().size();
+            Size::new(width, height)
+        }).unwrap_or_default();
+        self.area = Area::new(Origin::new(lx as i32 + sx, ly as i32 + sy), size);
+    }
 }
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct Seat {
     pub seat: SeatHandle,
     pub focused: Option<Rc<View>>,
+    /// A layer surface that has taken the keyboard away from the views,
+    /// e.g an application launcher.
+    pub focused_layer: Option<Rc<LayerSurface>>,
     pub action: Option<Action>,
     pub has_client_cursor: bool,
+    /// The cursor shown when the pointer is over no client, set from Lua
+    /// with `root.cursor`.
+    pub default_cursor: Option<String>,
     pub meta: bool,
-    pub drag_icons: HashSet<DragIcon>
+    pub drag_icons: Vec<DragIcon>
 }
 
 impl Seat {
@@ -48,10 +90,30 @@ impl Seat {
                ..Seat::default() }
     }
 
+    /// The name of the cursor shown when the pointer is over no client.
+    pub fn default_cursor(&self) -> String {
+        self.default_cursor
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CURSOR.to_string())
+    }
+
+    /// Moves the drag icons along with the pointer, damaging where they were
+    /// and where they are now.
+    pub fn move_drag_icons(&mut self, cursor: &Cursor, damage: &mut Damage) {
+        for drag_icon in self.drag_icons.iter_mut().filter(|drag_icon| drag_icon.mapped) {
+            damage.add_area(drag_icon.area);
+            drag_icon.follow(cursor);
+            damage.add_area(drag_icon.area);
+        }
+    }
+
     pub fn clear_focus(&mut self) {
         if let Some(focused_view) = self.focused.take() {
             focused_view.activate(false);
         }
+        if self.focused_layer.is_some() {
+            return
+        }
         dehandle!(@seat = {&self.seat};
                   seat.keyboard_clear_focus())
     }
@@ -71,10 +133,37 @@ impl Seat {
             views.insert(0, v);
         }
 
+        // The view gets the keyboard back once the layer surface is done.
+        if self.focused_layer.is_none() {
+            self.keyboard_enter(view.surface());
+        }
+    }
+
+    /// Gives the keyboard to a layer surface, regardless of which view is
+    /// focused.
+    pub fn focus_layer(&mut self, layer_surface: Rc<LayerSurface>) {
+        self.keyboard_enter(layer_surface.surface());
+        self.focused_layer = Some(layer_surface);
+    }
+
+    /// Takes the keyboard away from the focused layer surface and gives it
+    /// back to the focused view.
+    pub fn unfocus_layer(&mut self) {
+        self.focused_layer = None;
+        match self.focused.clone() {
+            Some(view) => self.keyboard_enter(view.surface()),
+            None => {
+                dehandle!(@seat = {&self.seat};
+                          seat.keyboard_clear_focus())
+            }
+        }
+    }
+
+    fn keyboard_enter(&mut self, surface: SurfaceHandle) {
         dehandle!(
             @seat = {&self.seat};
             if let Some(keyboard) = seat.get_keyboard() {
-                with_handles!([(keyboard: {keyboard}), (surface: {view.surface()})] => {
+                with_handles!([(keyboard: {keyboard}), (surface: {surface})] => {
                     seat.keyboard_notify_enter(surface,
                                                &mut keyboard.keycodes(),
                                                &mut keyboard.get_modifier_masks());
@@ -91,6 +180,26 @@ impl Seat {
             event.state() as u32));
     }
 
+    pub fn send_axis(&self, event: &AxisEvent) {
+        dehandle!(
+            @seat = {&self.seat};
+            seat.pointer_notify_axis(Duration::from_millis(event.time_msec() as _),
+                                     event.orientation(),
+                                     event.delta(),
+                                     event.delta_discrete(),
+                                     event.source()));
+    }
+
+    /// The modifiers held down on the keyboard of the seat, e.g for the
+    /// button bindings.
+    pub fn modifiers(&self) -> KeyboardModifier {
+        dehandle!(
+            @seat = {&self.seat};
+            seat.get_keyboard()
+                .and_then(|keyboard| keyboard.run(|keyboard| keyboard.get_modifiers()).ok())
+                .unwrap_or_else(KeyboardModifier::empty))
+    }
+
     pub fn move_view<O>(&mut self, cursor: &mut Cursor, view: &View, start: O)
         where O: Into<Option<Origin>>
     {
@@ -130,11 +239,87 @@ impl Seat {
         }).unwrap();
     }
 
-    pub fn view_at_pointer(views: &mut [Rc<View>],
+    /// Finds the view under the pointer.
+    ///
+    /// Unmanaged views are stacked above everything else, so they are hit
+    /// tested first.
+    pub fn view_at_pointer(unmanaged: &mut [Rc<View>],
+                           views: &mut [Rc<View>],
                            cursor: &mut Cursor)
                            -> (Option<Rc<View>>, Option<SurfaceHandle>, f64, f64) {
+        match Seat::view_at(unmanaged, cursor) {
+            (Some(view), surface, sx, sy) => (Some(view), surface, sx, sy),
+            _ => Seat::view_at(views, cursor)
+        }
+    }
+
+    /// Finds the layer surface under the pointer, only looking at the given
+    /// layers.
+    pub fn layer_surface_at(layer_surfaces: &[Rc<LayerSurface>],
+                            layers: &[Layer],
+                            cursor: &mut Cursor)
+                            -> (Option<Rc<LayerSurface>>, Option<SurfaceHandle>, f64, f64) {
+        for layer in layers {
+            let on_layer = layer_surfaces.iter()
+                                         .filter(|layer_surface| {
+                                                     layer_surface.mapped.get()
+                                                     && layer_surface.layer.get() == *layer
+                                                 });
+            for layer_surface in on_layer {
+                let (mut sx, mut sy) = (0.0, 0.0);
+                let surface = dehandle!(
+                    @surface = {layer_surface.surface()};
+                    let (lx, ly) = cursor.coords();
+                    let Origin { x, y } = layer_surface.geometry.get().origin;
+                    surface.surface_at(lx - x as f64, ly - y as f64, &mut sx, &mut sy)
+                );
+                if surface.is_some() {
+                    return (Some(layer_surface.clone()), surface, sx, sy)
+                }
+            }
+        }
+        (None, None, 0.0, 0.0)
+    }
+
+    /// Finds the surface under the pointer, taking the stacking of layer
+    /// surfaces and views into account.
+    fn surface_at_pointer(layer_surfaces: &[Rc<LayerSurface>],
+                          unmanaged: &mut [Rc<View>],
+                          views: &mut [Rc<View>],
+                          cursor: &mut Cursor)
+                          -> (Option<Rc<View>>, Option<SurfaceHandle>, f64, f64) {
+        let above = [Layer::Overlay, Layer::Top];
+        let below = [Layer::Bottom, Layer::Background];
+        if let (Some(_), surface, sx, sy) = Seat::layer_surface_at(layer_surfaces, &above, cursor) {
+            return (None, surface, sx, sy)
+        }
+        if let (Some(view), surface, sx, sy) = Seat::view_at_pointer(unmanaged, views, cursor) {
+            return (Some(view), surface, sx, sy)
+        }
+        match Seat::layer_surface_at(layer_surfaces, &below, cursor) {
+            (Some(_), surface, sx, sy) => (None, surface, sx, sy),
+            _ => (None, None, 0.0, 0.0)
+        }
+    }
+
+    fn view_at(views: &mut [Rc<View>],
+               cursor: &mut Cursor)
+               -> (Option<Rc<View>>, Option<SurfaceHandle>, f64, f64) {
         for view in views {
             match view.shell {
+                Shell::Xdg(ref shell) => {
+                    let (mut sx, mut sy) = (0.0, 0.0);
+                    let surface = dehandle!(
+                        @shell = {shell};
+                        let (lx, ly) = cursor.coords();
+                        let Origin {x: shell_x, y: shell_y} = view.origin.get();
+                        let (view_sx, view_sy) = (lx - shell_x as f64, ly - shell_y as f64);
+                        shell.surface_at(view_sx, view_sy, &mut sx, &mut sy)
+                    );
+                    if surface.is_some() {
+                        return (Some(view.clone()), surface, sx, sy)
+                    }
+                }
                 Shell::XdgV6(ref shell) => {
                     let (mut sx, mut sy) = (0.0, 0.0);
                     let surface = dehandle!(
@@ -148,6 +333,27 @@ impl Seat {
                         return (Some(view.clone()), surface, sx, sy)
                     }
                 }
+                Shell::XWayland(ref shell) => {
+                    let (mut sx, mut sy) = (0.0, 0.0);
+                    let surface = dehandle!(
+                        @shell = {shell};
+                        shell.surface()
+                    );
+                    let surface = match surface {
+                        Some(surface) => surface,
+                        None => continue
+                    };
+                    let surface = dehandle!(
+                        @surface = {surface};
+                        let (lx, ly) = cursor.coords();
+                        let Origin {x: shell_x, y: shell_y} = view.origin.get();
+                        let (view_sx, view_sy) = (lx - shell_x as f64, ly - shell_y as f64);
+                        surface.surface_at(view_sx, view_sy, &mut sx, &mut sy)
+                    );
+                    if surface.is_some() {
+                        return (Some(view.clone()), surface, sx, sy)
+                    }
+                }
             }
         }
         (None, None, 0.0, 0.0)
@@ -156,6 +362,8 @@ impl Seat {
     pub fn update_cursor_position(&mut self,
                                   cursor: &mut Cursor,
                                   xcursor_manager: &mut XCursorManager,
+                                  layer_surfaces: &[Rc<LayerSurface>],
+                                  unmanaged: &mut [Rc<View>],
                                   views: &mut [Rc<View>],
                                   time_msec: Option<u32>) {
         let time = if let Some(time_msec) = time_msec {
@@ -207,7 +415,10 @@ impl Seat {
                 });
             }
             _ => {
-                let (_view, surface, sx, sy) = Seat::view_at_pointer(views, cursor);
+                let (_view, surface, sx, sy) = Seat::surface_at_pointer(layer_surfaces,
+                                                                         unmanaged,
+                                                                         views,
+                                                                         cursor);
                 match surface {
                     Some(surface) => {
                         dehandle!(
@@ -219,7 +430,7 @@ impl Seat {
                     }
                     None => {
                         if self.has_client_cursor {
-                            xcursor_manager.set_cursor_image("left_ptr".to_string(), cursor);
+                            xcursor_manager.set_cursor_image(self.default_cursor(), cursor);
                             self.has_client_cursor = false;
                         }
                         dehandle!(
@@ -236,23 +447,93 @@ impl Seat {
 struct DragIconHandler;
 
 impl wlroots::DragIconHandler for DragIconHandler {
-    fn on_map(&mut self, _: CompositorHandle, _: DragIconHandle) {
-        // TODO damage the drag icon surface location
+    fn on_map(&mut self, compositor: CompositorHandle, drag_icon: DragIconHandle) {
+        dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut damage,
+                         ref cursor,
+                         .. } = *server;
+            @cursor = {cursor};
+            let mapped = seat.drag_icons.iter_mut().find(|other| other.handle == drag_icon);
+            if let Some(mapped) = mapped {
+                mapped.mapped = true;
+                mapped.follow(cursor);
+                damage.add_area(mapped.area);
+            }
+        );
     }
 
-    fn on_unmap(&mut self, _: CompositorHandle, _: DragIconHandle) {
-        // TODO damage the drag icon surface location
+    fn on_unmap(&mut self, compositor: CompositorHandle, drag_icon: DragIconHandle) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut damage,
+                         .. } = *server;
+            let unmapped = seat.drag_icons.iter_mut().find(|other| other.handle == drag_icon);
+            if let Some(unmapped) = unmapped {
+                unmapped.mapped = false;
+                damage.add_area(unmapped.area);
+            }
+        }).unwrap();
     }
 
     fn destroyed(&mut self, compositor: CompositorHandle, drag_icon: DragIconHandle) {
         with_handles!([(compositor: {compositor})] => {
             let server: &mut Server = compositor.into();
-            server.seat.drag_icons.remove(&DragIcon{ handle: drag_icon });
+            let Server { ref mut seat,
+                         ref mut damage,
+                         .. } = *server;
+            {
+                let destroyed = seat.drag_icons.iter().find(|other| other.handle == drag_icon);
+                if let Some(destroyed) = destroyed.filter(|destroyed| destroyed.mapped) {
+                    damage.add_area(destroyed.area);
+                }
+            }
+            seat.drag_icons.retain(|other| other.handle != drag_icon);
         }).unwrap();
     }
 }
 
+/// Keeps a drag icon where it should be when the client draws a new one,
+/// or moves it with an offset.
+struct DragIconSurface;
+
+impl SurfaceHandler for DragIconSurface {
+    fn on_commit(&mut self, compositor: CompositorHandle, surface: SurfaceHandle) {
+        dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut damage,
+                         ref cursor,
+                         .. } = *server;
+            @cursor = {cursor};
+            let committed = seat.drag_icons
+                                .iter_mut()
+                                .find(|drag_icon| drag_icon.surface() == surface);
+            if let Some(committed) = committed.filter(|committed| committed.mapped) {
+                damage.add_area(committed.area);
+                committed.follow(cursor);
+                damage.add_area(committed.area);
+            }
+        );
+    }
+}
+
 impl SeatHandler for SeatManager {
+    fn primary_selection_changed(&mut self, compositor: CompositorHandle, _: SeatHandle) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            compositor::read_primary_selection(server);
+        }).unwrap();
+    }
+
+    fn selection_changed(&mut self, _: CompositorHandle, _: SeatHandle) {
+        compositor::clipboard_changed();
+    }
+
     fn cursor_set(&mut self, compositor: CompositorHandle, _: SeatHandle, event: &SetCursorEvent) {
         if let Some(surface) = event.surface() {
             dehandle!(
@@ -279,9 +560,44 @@ impl SeatHandler for SeatManager {
         with_handles!([(compositor: {compositor})] => {
             let server: &mut Server = compositor.into();
             let Server { ref mut seat, .. } = *server;
-            seat.drag_icons.insert(DragIcon { handle: drag_icon });
+            seat.drag_icons.push(DragIcon { handle: drag_icon,
+                                            area: Area::default(),
+                                            mapped: false });
         }).unwrap();
-        (Some(Box::new(DragIconHandler)), None)
+        (Some(Box::new(DragIconHandler)), Some(Box::new(DragIconSurface)))
+    }
+
+    /// A drag started, with or without an icon.
+    fn pointer_grabbed(&mut self, compositor: CompositorHandle, _: SeatHandle, _: &PointerGrab) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            // The drop target gets the pointer, rather than a view being
+            // moved or resized along.
+            server.seat.action = None;
+        }).unwrap();
+    }
+
+    /// The drag ended, with a drop or otherwise.
+    fn pointer_released(&mut self, compositor: CompositorHandle, _: SeatHandle, _: &PointerGrab) {
+        dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            // The pointer goes back to whatever is under it.
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None)
+        );
     }
 }
 
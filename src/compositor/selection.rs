@@ -0,0 +1,256 @@
+//! The selections of the seat: the clipboard, and the primary selection
+//! (i.e what is pasted with a middle click).
+//!
+//! Both are shared between Wayland and XWayland clients. Lua is told with
+//! `selection::changed` when either of them changes, and gets the text of
+//! the primary selection with `selection()`.
+//!
+//! What is selected has to be sent to us by the client that owns it, which
+//! can't happen while we wait for it. So it is read in the background
+//! whenever the selection changes.
+
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+use glib::{self, Continue, IOCondition, SourceId};
+use nix::fcntl::{O_CLOEXEC, O_NONBLOCK};
+use nix::unistd::{close, pipe2, read};
+use nix::{self, errno};
+use rlua::ToLua;
+use wlroots::{self, SeatHandle};
+
+use awesome::{self, LUA};
+use compositor::Server;
+
+/// The mime types the selection can be read as text with, best first.
+const TEXT_MIME_TYPES: &[&str] = &["text/plain;charset=utf-8",
+                                   "UTF8_STRING",
+                                   "text/plain",
+                                   "STRING",
+                                   "TEXT"];
+
+/// How long a client may take to send a selection, in milliseconds.
+const TRANSFER_TIMEOUT_MS: u32 = 1000;
+
+/// The most that is read of the primary selection, so that a client can't
+/// make us run out of memory.
+const MAX_SELECTION_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Selection {
+    /// The text of the primary selection, once it was read.
+    pub primary: Option<String>,
+    /// Counts the changes of the primary selection, so that the text of an
+    /// older selection isn't stored once it has been read.
+    generation: u64
+}
+
+/// Starts reading the primary selection of the seat as text, because it
+/// changed.
+pub fn read_primary_selection(server: &mut Server) {
+    let selection = &mut server.selection;
+    selection.generation += 1;
+    selection.primary = None;
+    let generation = selection.generation;
+    let fd = with_handles!([(seat: {&server.seat.seat})] => {
+        let source = seat.primary_selection()?;
+        let mime_types = source.mime_types();
+        let mime_type = TEXT_MIME_TYPES.iter().find(|mime_type| {
+                                                        mime_types.iter().any(|m| m == *mime_type)
+                                                    })?;
+        let (read_fd, write_fd) = open_pipe("the primary selection")?;
+        source.send(mime_type, write_fd);
+        close(write_fd).ok();
+        Some(read_fd)
+    }).ok()
+      .and_then(|fd| fd);
+    let fd = match fd {
+        Some(fd) => fd,
+        // The selection was cleared, or it isn't text.
+        None => {
+            emit_changed("primary");
+            return
+        }
+    };
+    receive(fd,
+            MAX_SELECTION_SIZE,
+            "the primary selection",
+            move |contents| store_primary_selection(generation, contents));
+}
+
+fn store_primary_selection(generation: u64, contents: Option<Vec<u8>>) {
+    let stored = dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        // The selection changed again while it was being read.
+        if server.selection.generation != generation {
+            return false
+        }
+        server.selection.primary =
+            contents.map(|contents| String::from_utf8_lossy(&contents).into_owned());
+        true
+    );
+    if stored {
+        emit_changed("primary");
+    }
+}
+
+/// Tells Lua that the clipboard of the seat changed.
+pub fn clipboard_changed() {
+    emit_changed("clipboard");
+}
+
+fn open_pipe(what: &str) -> Option<(RawFd, RawFd)> {
+    match pipe2(O_CLOEXEC | O_NONBLOCK) {
+        Ok(fds) => Some(fds),
+        Err(err) => {
+            warn!("Could not read {}: {:?}", what, err);
+            None
+        }
+    }
+}
+
+/// Reads what a client sends through the pipe in the background, and calls
+/// `done` with it once all of it was read.
+///
+/// `done` gets `None` if the client took too long, or sent more than
+/// `max_size` bytes.
+fn receive<F>(fd: RawFd, max_size: usize, what: &'static str, done: F)
+    where F: FnOnce(Option<Vec<u8>>) + Send + 'static
+{
+    let contents = Arc::new(Mutex::new(Vec::new()));
+    let read_contents = contents.clone();
+    let condition = IOCondition::IN | IOCondition::HUP | IOCondition::ERR;
+    watch(fd,
+          condition,
+          move || read_available(fd, &mut read_contents.lock().unwrap(), max_size),
+          move |result| match result {
+              Ok(()) => done(Some(mem::replace(&mut contents.lock().unwrap(), Vec::new()))),
+              Err(reason) => {
+                  warn!("Could not read {}: {}", what, reason);
+                  done(None)
+              }
+          });
+}
+
+/// Reads what the client sent so far.
+///
+/// Returns whether there is more to read.
+fn read_available(fd: RawFd, contents: &mut Vec<u8>, max_size: usize) -> Result<bool, String> {
+    let mut buffer = [0; 4096];
+    loop {
+        match read(fd, &mut buffer) {
+            Ok(0) => return Ok(false),
+            Ok(count) => {
+                contents.extend_from_slice(&buffer[..count]);
+                if contents.len() > max_size {
+                    return Err("it is too big".into())
+                }
+            }
+            Err(nix::Error::Sys(errno::EAGAIN)) => return Ok(true),
+            Err(err) => return Err(format!("{:?}", err))
+        }
+    }
+}
+
+/// A pipe that is being watched by `watch`.
+struct Watch<F> {
+    fd: RawFd,
+    finish: Option<F>,
+    /// Whichever of these is left when the transfer ends is removed.
+    watch_source: Option<SourceId>,
+    timeout_source: Option<SourceId>
+}
+
+impl<F: FnOnce(Result<(), String>)> Watch<F> {
+    fn end(&mut self, result: Result<(), String>) {
+        for source in self.watch_source.take().into_iter().chain(self.timeout_source.take()) {
+            glib::source_remove(source);
+        }
+        close(self.fd).ok();
+        if let Some(finish) = self.finish.take() {
+            finish(result);
+        }
+    }
+}
+
+/// Calls `transfer` whenever the pipe is ready, until it returns that there
+/// is nothing more to transfer or fails, and closes the pipe after.
+///
+/// `finish` is called with how it went, which is an error if the client
+/// took too long.
+fn watch<T, F>(fd: RawFd, condition: IOCondition, mut transfer: T, finish: F)
+    where T: FnMut() -> Result<bool, String> + Send + 'static,
+          F: FnOnce(Result<(), String>) + Send + 'static
+{
+    let watch = Arc::new(Mutex::new(Watch { fd,
+                                            finish: Some(finish),
+                                            watch_source: None,
+                                            timeout_source: None }));
+    let timed_out = watch.clone();
+    let timeout_source = glib::timeout_add(TRANSFER_TIMEOUT_MS, move || {
+                                               let mut watch = timed_out.lock().unwrap();
+                                               watch.timeout_source = None;
+                                               watch.end(Err("the client took too long".into()));
+                                               Continue(false)
+                                           });
+    let ready = watch.clone();
+    let watch_source = glib::unix_fd_add(fd, condition, move |_, _| {
+                                             let result = match transfer() {
+                                                 Ok(true) => return Continue(true),
+                                                 Ok(false) => Ok(()),
+                                                 Err(reason) => Err(reason)
+                                             };
+                                             let mut watch = ready.lock().unwrap();
+                                             watch.watch_source = None;
+                                             watch.end(result);
+                                             Continue(false)
+                                         });
+    let mut watch = watch.lock().unwrap();
+    watch.timeout_source = Some(timeout_source);
+    watch.watch_source = Some(watch_source);
+}
+
+/// Emits `selection::changed` with which selection changed ("clipboard" or
+/// "primary"), once Lua can be called.
+fn emit_changed(selection: &'static str) {
+    glib::idle_add(move || {
+                       LUA.with(|lua| {
+                                    let lua = lua.borrow();
+                                    let signal = match selection.to_lua(&*lua) {
+                                        Ok(value) => ("selection::changed".into(), value),
+                                        Err(err) => {
+                                            warn!("Could not emit selection::changed: {:?}", err);
+                                            return
+                                        }
+                                    };
+                                    if let Err(err) = awesome::global_emit_signal(&*lua, signal) {
+                                        warn!("Could not emit selection::changed: {:?}", err);
+                                    }
+                                });
+                       Continue(false)
+                   });
+}
+
+/// Lets XWayland clients use the selections of the seat, and vice versa.
+pub fn share_selections(compositor: &mut wlroots::Compositor, seat: &SeatHandle) {
+    if let Some(xwayland) = compositor.xwayland.as_mut() {
+        with_handles!([(seat: {seat})] => {
+            xwayland.set_seat(seat)
+        }).unwrap();
+    }
+}
+
+/// The text of the primary selection, from outside of a compositor callback
+/// (e.g from Lua).
+///
+/// This is `None` if there is no selection, it isn't text or it is still
+/// being read.
+pub fn primary_selection() -> Option<String> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.selection.primary.clone()
+    )
+}
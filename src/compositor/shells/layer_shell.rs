@@ -0,0 +1,395 @@
+//! Support for wlr-layer-shell, which is used by panels, docks, launchers,
+//! notification daemons and wallpaper setters.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use wlroots::layer_shell::{Anchor, Layer};
+use wlroots::{Area, CompositorHandle, LayerShellHandler, LayerShellManagerHandler,
+              LayerSurfaceHandle, Origin, OutputHandle, OutputLayoutHandle, Size, SurfaceHandle,
+              SurfaceHandler};
+
+use awesome::{self, LUA};
+use compositor::Server;
+
+/// A surface created by a layer shell client.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LayerSurface {
+    pub handle: LayerSurfaceHandle,
+    /// The output this surface was placed on.
+    pub output: OutputHandle,
+    /// The layer the surface asked to be in as of its last commit.
+    pub layer: Cell<Layer>,
+    /// Where the surface was placed, in output layout coordinates.
+    pub geometry: Cell<Area>,
+    pub mapped: Cell<bool>
+}
+
+impl LayerSurface {
+    pub fn new(handle: LayerSurfaceHandle, output: OutputHandle, layer: Layer) -> Self {
+        LayerSurface { handle,
+                       output,
+                       layer: Cell::new(layer),
+                       geometry: Cell::new(Area::default()),
+                       mapped: Cell::new(false) }
+    }
+
+    pub fn surface(&self) -> SurfaceHandle {
+        with_handles!([(layer_surface: {&self.handle})] => {
+            layer_surface.surface()
+        }).unwrap()
+    }
+
+    /// Whether the surface wants to receive keyboard input.
+    ///
+    /// Only surfaces above the views are allowed to take the keyboard.
+    pub fn keyboard_interactive(&self) -> bool {
+        let layer = self.layer.get();
+        if layer != Layer::Top && layer != Layer::Overlay {
+            return false
+        }
+        with_handles!([(layer_surface: {&self.handle})] => {
+            layer_surface.current().keyboard_interactive()
+        }).unwrap_or(false)
+    }
+
+    pub fn for_each_surface(&self, f: &mut FnMut(SurfaceHandle, i32, i32)) {
+        with_handles!([(surface: {self.surface()})] => {
+            surface.for_each_surface(f);
+        }).unwrap();
+    }
+
+    /// Places the surface within the bounds of its output and tells the
+    /// client what size it is going to be.
+    ///
+    /// If the surface reserves an exclusive zone it is removed from
+    /// `usable_area`. Only surfaces whose exclusivity matches `exclusive` are
+    /// arranged, so that all exclusive zones are known before the rest of the
+    /// surfaces are placed.
+    fn arrange(&self, full_area: Area, usable_area: &mut Area, exclusive: bool) {
+        with_handles!([(layer_surface: {&self.handle})] => {
+            let state = layer_surface.current();
+            let exclusive_zone = state.exclusive_zone();
+            if (exclusive_zone > 0) != exclusive {
+                return
+            }
+            let bounds = if exclusive_zone == -1 { full_area } else { *usable_area };
+            let anchor = state.anchor();
+            let (margin_top, margin_right, margin_bottom, margin_left) = state.margin();
+            let (margin_top, margin_right, margin_bottom, margin_left) =
+                (margin_top as i32, margin_right as i32, margin_bottom as i32, margin_left as i32);
+            let (desired_width, desired_height) = state.desired_size();
+            let Size { mut width,
+                       mut height } = Size::new(desired_width as i32, desired_height as i32);
+            let horizontal = Anchor::LEFT | Anchor::RIGHT;
+            let vertical = Anchor::TOP | Anchor::BOTTOM;
+
+            let mut x;
+            if width == 0 {
+                x = bounds.origin.x;
+                width = bounds.size.width;
+            } else if anchor.contains(horizontal) {
+                x = bounds.origin.x + bounds.size.width / 2 - width / 2;
+            } else if anchor.contains(Anchor::LEFT) {
+                x = bounds.origin.x;
+            } else if anchor.contains(Anchor::RIGHT) {
+                x = bounds.origin.x + bounds.size.width - width;
+            } else {
+                x = bounds.origin.x + bounds.size.width / 2 - width / 2;
+            }
+            if anchor.contains(horizontal) {
+                x += margin_left;
+                width -= margin_left + margin_right;
+            } else if anchor.contains(Anchor::LEFT) {
+                x += margin_left;
+            } else if anchor.contains(Anchor::RIGHT) {
+                x -= margin_right;
+            }
+
+            let mut y;
+            if height == 0 {
+                y = bounds.origin.y;
+                height = bounds.size.height;
+            } else if anchor.contains(vertical) {
+                y = bounds.origin.y + bounds.size.height / 2 - height / 2;
+            } else if anchor.contains(Anchor::TOP) {
+                y = bounds.origin.y;
+            } else if anchor.contains(Anchor::BOTTOM) {
+                y = bounds.origin.y + bounds.size.height - height;
+            } else {
+                y = bounds.origin.y + bounds.size.height / 2 - height / 2;
+            }
+            if anchor.contains(vertical) {
+                y += margin_top;
+                height -= margin_top + margin_bottom;
+            } else if anchor.contains(Anchor::TOP) {
+                y += margin_top;
+            } else if anchor.contains(Anchor::BOTTOM) {
+                y -= margin_bottom;
+            }
+
+            if width < 0 || height < 0 {
+                warn!("Closing layer surface with a negative size");
+                layer_surface.close();
+                return
+            }
+
+            if exclusive_zone > 0 {
+                // An exclusive zone is only meaningful when the surface is
+                // attached to exactly one edge, optionally stretching along it.
+                if anchor == Anchor::TOP || anchor == Anchor::TOP | horizontal {
+                    let reserved = exclusive_zone + margin_top;
+                    usable_area.origin.y += reserved;
+                    usable_area.size.height -= reserved;
+                } else if anchor == Anchor::BOTTOM || anchor == Anchor::BOTTOM | horizontal {
+                    usable_area.size.height -= exclusive_zone + margin_bottom;
+                } else if anchor == Anchor::LEFT || anchor == Anchor::LEFT | vertical {
+                    let reserved = exclusive_zone + margin_left;
+                    usable_area.origin.x += reserved;
+                    usable_area.size.width -= reserved;
+                } else if anchor == Anchor::RIGHT || anchor == Anchor::RIGHT | vertical {
+                    usable_area.size.width -= exclusive_zone + margin_right;
+                }
+            }
+
+            self.geometry.set(Area::new(Origin::new(x, y), Size::new(width, height)));
+            layer_surface.configure(width as u32, height as u32);
+        }).unwrap();
+    }
+}
+
+/// Arranges the layer surfaces on an output.
+///
+/// Returns the area of the output that is not reserved by any exclusive
+/// zones, in output layout coordinates.
+pub fn arrange_layers(layout: &mut OutputLayoutHandle,
+                      output: &OutputHandle,
+                      layer_surfaces: &[Rc<LayerSurface>])
+                      -> Area {
+    let full_area = with_handles!([(layout: {&mut *layout}), (output: {output})] => {
+        layout.get_box(Some(output))
+    }).unwrap();
+    let mut usable_area = full_area;
+    for &exclusive in &[true, false] {
+        for layer in &[Layer::Overlay, Layer::Top, Layer::Bottom, Layer::Background] {
+            let on_layer = layer_surfaces.iter()
+                                         .filter(|layer_surface| {
+                                                     layer_surface.output == *output
+                                                     && layer_surface.layer.get() == *layer
+                                                 });
+            for layer_surface in on_layer {
+                layer_surface.arrange(full_area, &mut usable_area, exclusive);
+            }
+        }
+    }
+    usable_area
+}
+
+/// Arranges the layer surfaces on an output and lets Lua know about the new
+/// workarea of that output.
+///
+/// This must not be called while the compositor is borrowed.
+pub fn arrange_output(compositor: CompositorHandle, output: OutputHandle) {
+    let workarea = with_handles!([(compositor: {compositor})] => {
+        let server: &mut Server = compositor.into();
+        let Server { ref mut layout,
+                     ref outputs,
+                     ref layer_surfaces,
+                     .. } = *server;
+        // The output might have been unplugged in the meantime.
+        if outputs.contains(&output) {
+            Some(arrange_layers(layout, &output, layer_surfaces))
+        } else {
+            None
+        }
+    }).unwrap();
+    let workarea = match workarea {
+        Some(workarea) => workarea,
+        None => return
+    };
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 if let Err(err) = awesome::update_workarea(&*lua, &output, workarea) {
+                     warn!("Could not update the workarea: {:?}", err);
+                 }
+             });
+}
+
+fn layer_surface_output(compositor: CompositorHandle,
+                        layer_surface: &LayerSurfaceHandle)
+                        -> Option<OutputHandle> {
+    with_handles!([(compositor: {compositor})] => {
+        let server: &mut Server = compositor.into();
+        server.layer_surfaces
+              .iter()
+              .find(|surface| surface.handle == *layer_surface)
+              .map(|surface| surface.output.clone())
+    }).unwrap()
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LayerShell;
+
+impl LayerShellHandler for LayerShell {
+    fn on_commit(&mut self,
+                 compositor: CompositorHandle,
+                 _: SurfaceHandle,
+                 layer_surface: LayerSurfaceHandle) {
+        let layer = with_handles!([(layer_surface: {&layer_surface})] => {
+            layer_surface.current().layer()
+        }).unwrap();
+        let old_geometry = with_handles!([(compositor: {compositor.clone()})] => {
+            let server: &mut Server = compositor.into();
+            server.layer_surfaces
+                  .iter()
+                  .find(|surface| surface.handle == layer_surface)
+                  .map(|surface| {
+                           surface.layer.set(layer);
+                           surface.geometry.get()
+                       })
+        }).unwrap();
+        if let Some(output) = layer_surface_output(compositor.clone(), &layer_surface) {
+            arrange_output(compositor.clone(), output);
+        }
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref layer_surfaces,
+                         ref mut damage,
+                         .. } = *server;
+            let committed = layer_surfaces.iter().find(|surface| surface.handle == layer_surface);
+            if let (Some(committed), Some(old_geometry)) = (committed, old_geometry) {
+                let geometry = committed.geometry.get();
+                if geometry != old_geometry {
+                    damage.add_area(old_geometry);
+                }
+                damage.add_area(geometry);
+            }
+        }).unwrap();
+    }
+
+    fn on_map(&mut self,
+              compositor: CompositorHandle,
+              _: SurfaceHandle,
+              layer_surface: LayerSurfaceHandle) {
+        dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            let mapped = layer_surfaces.iter()
+                                       .find(|surface| surface.handle == layer_surface)
+                                       .cloned();
+            if let Some(mapped) = mapped {
+                mapped.mapped.set(true);
+                damage.add_area(mapped.geometry.get());
+                if mapped.keyboard_interactive() {
+                    seat.focus_layer(mapped);
+                }
+            };
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None)
+        );
+    }
+
+    fn on_unmap(&mut self,
+                compositor: CompositorHandle,
+                _: SurfaceHandle,
+                layer_surface: LayerSurfaceHandle) {
+        dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            if let Some(unmapped) = layer_surfaces.iter()
+                                                  .find(|surface| surface.handle == layer_surface) {
+                unmapped.mapped.set(false);
+                damage.add_area(unmapped.geometry.get());
+            }
+            if seat.focused_layer.as_ref().map(|focused| focused.handle == layer_surface)
+                                          .unwrap_or(false) {
+                seat.unfocus_layer();
+            };
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None)
+        );
+    }
+
+    fn destroyed(&mut self, compositor: CompositorHandle, layer_surface: LayerSurfaceHandle) {
+        let output = layer_surface_output(compositor.clone(), &layer_surface);
+        with_handles!([(compositor: {compositor.clone()})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut layer_surfaces,
+                         .. } = *server;
+            if seat.focused_layer.as_ref().map(|focused| focused.handle == layer_surface)
+                                          .unwrap_or(false) {
+                seat.unfocus_layer();
+            }
+            layer_surfaces.retain(|surface| surface.handle != layer_surface);
+        }).unwrap();
+        // Give the space it reserved back to the views.
+        if let Some(output) = output {
+            arrange_output(compositor, output);
+        }
+    }
+}
+
+pub struct LayerShellManager;
+
+impl LayerShellManagerHandler for LayerShellManager {
+    fn new_surface(&mut self,
+                   compositor: CompositorHandle,
+                   layer_surface: LayerSurfaceHandle)
+                   -> (Option<Box<LayerShellHandler>>, Option<Box<SurfaceHandler>>) {
+        let placed = with_handles!([(compositor: {compositor}),
+                                    (layer_surface: {&layer_surface})] => {
+            let server: &mut Server = compositor.into();
+            // If the client lets us choose, put it on the first output.
+            // TODO Use the output that has the focused view
+            let output = layer_surface.output().or_else(|| server.outputs.first().cloned());
+            match output {
+                Some(output) => {
+                    layer_surface.set_output(&output);
+                    let layer = layer_surface.current().layer();
+                    let surface = LayerSurface::new(layer_surface.weak_reference(),
+                                                    output,
+                                                    layer);
+                    server.layer_surfaces.push(Rc::new(surface));
+                    true
+                }
+                None => {
+                    warn!("No output to put layer surface on, closing it");
+                    layer_surface.close();
+                    false
+                }
+            }
+        }).unwrap();
+        if placed {
+            (Some(Box::new(LayerShell)), None)
+        } else {
+            (None, None)
+        }
+    }
+}
@@ -1,36 +1,91 @@
+mod layer_shell;
+mod xdg;
 mod xdg_v6;
+mod xwayland;
 
+pub use self::layer_shell::*;
+pub use self::xdg::*;
 pub use self::xdg_v6::*;
+pub use self::xwayland::*;
 
-use wlroots::{Area, HandleResult, SurfaceHandle, XdgV6ShellSurfaceHandle};
+use std::rc::Rc;
+
+use wlroots::{Area, HandleResult, Origin, Size, SurfaceHandle, XWaylandSurfaceHandle,
+              XdgShellSurfaceHandle, XdgV6ShellSurfaceHandle};
+
+use compositor::View;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Shell {
-    XdgV6(XdgV6ShellSurfaceHandle) /* TODO WlShell
-                                    * TODO Xdg */
+    Xdg(XdgShellSurfaceHandle),
+    XdgV6(XdgV6ShellSurfaceHandle),
+    XWayland(XWaylandSurfaceHandle) /* TODO WlShell */
 }
 
 impl Shell {
     /// Get a wlr surface from the shell.
     pub fn surface(&mut self) -> SurfaceHandle {
         match *self {
+            Shell::Xdg(ref mut shell) => {
+                shell.run(|shell| shell.surface())
+                     .expect("An xdg client did not provide us a surface")
+            }
             Shell::XdgV6(ref mut shell) => {
                 shell.run(|shell| shell.surface())
                      .expect("An xdg v6 client did not provide us a surface")
             }
+            Shell::XWayland(ref mut shell) => {
+                shell.run(|shell| shell.surface())
+                     .ok()
+                     .and_then(|surface| surface)
+                     .expect("An XWayland client did not provide us a surface")
+            }
         }
     }
 
     /// Get the geometry of a shell.
     pub fn geometry(&mut self) -> HandleResult<Area> {
         match *self {
-            Shell::XdgV6(ref mut shell) => shell.run(|shell| shell.geometry())
+            Shell::Xdg(ref mut shell) => shell.run(|shell| shell.geometry()),
+            Shell::XdgV6(ref mut shell) => shell.run(|shell| shell.geometry()),
+            Shell::XWayland(ref mut shell) => {
+                shell.run(|shell| {
+                               let (x, y) = shell.coords();
+                               let (width, height) = shell.dimensions();
+                               Area::new(Origin::new(x as i32, y as i32),
+                                         Size::new(width as i32, height as i32))
+                           })
+            }
         }
     }
 }
 
+impl Into<Shell> for XdgShellSurfaceHandle {
+    fn into(self) -> Shell {
+        Shell::Xdg(self)
+    }
+}
+
 impl Into<Shell> for XdgV6ShellSurfaceHandle {
     fn into(self) -> Shell {
         Shell::XdgV6(self)
     }
 }
+
+impl Into<Shell> for XWaylandSurfaceHandle {
+    fn into(self) -> Shell {
+        Shell::XWayland(self)
+    }
+}
+
+/// Where a popup is in the output layout, given its parent surface and its
+/// geometry relative to that parent.
+///
+/// `None` if the parent isn't in any of the views.
+pub fn popup_box(views: &[Rc<View>], parent: &SurfaceHandle, geometry: Area) -> Option<Area> {
+    let parent_box = views.iter()
+                          .filter_map(|view| view.surface_box(parent))
+                          .next()?;
+    let Origin { x, y } = parent_box.origin;
+    Some(Area::new(Origin::new(x + geometry.origin.x, y + geometry.origin.y), geometry.size))
+}
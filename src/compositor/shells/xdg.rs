@@ -0,0 +1,216 @@
+use compositor::{self, Action, Server, Shell, View, ViewSurface};
+use wlroots::{CompositorHandle, Origin, SurfaceHandle, SurfaceHandler, XdgShellHandler,
+              XdgShellManagerHandler, XdgShellState::*, XdgShellSurfaceHandle};
+
+use std::rc::Rc;
+use wlroots::xdg_shell_events::{MoveEvent, ResizeEvent};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Xdg {
+    shell_surface: XdgShellSurfaceHandle
+}
+
+impl Xdg {
+    pub fn new() -> Self {
+        Xdg { ..Xdg::default() }
+    }
+}
+
+impl XdgShellHandler for Xdg {
+    fn resize_request(&mut self,
+                      compositor: CompositorHandle,
+                      _: SurfaceHandle,
+                      shell_surface: XdgShellSurfaceHandle,
+                      event: &ResizeEvent) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut cursor,
+                         .. } = *server;
+            let resizing_shell = shell_surface.into();
+
+            if let Some(view) = views.iter().find(|view| view.shell == resizing_shell).cloned() {
+                seat.begin_resize(cursor, view.clone(), views, event.edges())
+            }
+        }).unwrap();
+    }
+
+    fn move_request(&mut self,
+                    compositor: CompositorHandle,
+                    _: SurfaceHandle,
+                    shell_surface: XdgShellSurfaceHandle,
+                    _: &MoveEvent) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let ref mut seat = server.seat;
+            let ref mut cursor = server.cursor;
+
+            if let Some(ref mut view) = seat.focused {
+                let shell: Shell = shell_surface.into();
+                let action = &mut seat.action;
+                if view.shell == shell {
+                    with_handles!([(cursor: {cursor})] => {
+                        let (lx, ly) = cursor.coords();
+                        let Origin { x: shell_x, y: shell_y } = view.origin.get();
+                        let (view_sx, view_sy) = (lx - shell_x as f64, ly - shell_y as f64);
+                        let start = Origin::new(view_sx as _, view_sy as _);
+                        *action = Some(Action::Moving { start: start });
+                    }).unwrap();
+                }
+            }
+        }).unwrap();
+    }
+
+    fn on_commit(&mut self,
+                 compositor: CompositorHandle,
+                 _: SurfaceHandle,
+                 shell_surface: XdgShellSurfaceHandle) {
+        let configure_serial = {
+            with_handles!([(shell_surface: {shell_surface.clone()})] => {
+                shell_surface.configure_serial()
+            }).unwrap()
+        };
+
+        let popup = with_handles!([(shell_surface: {shell_surface.clone()})] => {
+            match shell_surface.state() {
+                Some(&mut Popup(ref popup)) => Some((popup.parent(), popup.geometry())),
+                _ => None
+            }
+        }).unwrap();
+        let surface = shell_surface.into();
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut views,
+                         ref mut damage,
+                         .. } = *server;
+
+            if let Some(view) = views.iter().find(|view| view.shell == surface).cloned() {
+                if let Some(move_resize) = view.pending_move_resize.get() {
+                    if move_resize.serial >= configure_serial {
+                        let Origin {mut x, mut y} = view.origin.get();
+                        if move_resize.update_x {
+                            x  = move_resize.area.origin.x + move_resize.area.size.width -
+                                 view.get_size().width;
+                        }
+                        if move_resize.update_y {
+                            y  = move_resize.area.origin.y + move_resize.area.size.height -
+                                 view.get_size().height;
+                        }
+
+                        view.origin.set(Origin { x, y });
+
+                        if move_resize.serial == configure_serial {
+                            view.pending_move_resize.set(None);
+                        }
+                    }
+                }
+                view.damage(damage);
+            } else if let Some((parent, geometry)) = popup {
+                // Popups aren't views, they are placed relative to their
+                // parent in one.
+                match compositor::popup_box(views, &parent, geometry) {
+                    Some(popup_box) => damage.add_area(popup_box),
+                    None => damage.add_whole()
+                }
+            }
+        }).unwrap();
+    }
+
+    fn map_request(&mut self,
+                   compositor: CompositorHandle,
+                   _: SurfaceHandle,
+                   shell_surface_handle: XdgShellSurfaceHandle) {
+        let is_toplevel = with_handles!([(shell_surface: {&shell_surface_handle})] => {
+            match shell_surface.state().unwrap() {
+                TopLevel(_) => true,
+                _ => false
+            }
+        }).unwrap();
+        let mapped = dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            let mapped = if is_toplevel {
+                let view = Rc::new(View::new(Shell::Xdg(shell_surface_handle.into())));
+                views.push(view.clone());
+                view.damage(damage);
+                seat.focus_view(view.clone(), views);
+                Some(view)
+            } else {
+                None
+            };
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None);
+            mapped
+        );
+        if let Some(view) = mapped {
+            compositor::view_mapped(view);
+        }
+    }
+
+    fn unmap_request(&mut self,
+                     compositor: CompositorHandle,
+                     _: SurfaceHandle,
+                     shell_surface: XdgShellSurfaceHandle) {
+        let unmapped = dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            let destroyed_shell = shell_surface.into();
+            let unmapped = views.iter()
+                                .position(|view| view.shell == destroyed_shell)
+                                .map(|pos| views.remove(pos));
+            if let Some(ref view) = unmapped {
+                view.damage(damage);
+            }
+
+            if views.len() > 0 {
+                seat.focus_view(views[0].clone(), views);
+            } else {
+                seat.clear_focus();
+            };
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None);
+            unmapped
+        );
+        if let Some(view) = unmapped {
+            compositor::view_unmapped(&view);
+        }
+    }
+}
+
+pub struct XdgShellManager;
+
+impl XdgShellManagerHandler for XdgShellManager {
+    fn new_surface(&mut self,
+                   _: CompositorHandle,
+                   _: XdgShellSurfaceHandle)
+                   -> (Option<Box<XdgShellHandler>>, Option<Box<SurfaceHandler>>) {
+        (Some(Box::new(Xdg::new())), Some(Box::new(ViewSurface)))
+    }
+}
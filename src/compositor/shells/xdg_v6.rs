@@ -1,4 +1,4 @@
-use compositor::{Action, Server, Shell, View};
+use compositor::{self, Action, Server, Shell, View, ViewSurface};
 use wlroots::{CompositorHandle, Origin, SurfaceHandle, SurfaceHandler, XdgV6ShellHandler,
               XdgV6ShellManagerHandler, XdgV6ShellState::*, XdgV6ShellSurfaceHandle};
 
@@ -72,10 +72,18 @@ impl XdgV6ShellHandler for XdgV6 {
             }).unwrap()
         };
 
+        let popup = with_handles!([(shell_surface: {shell_surface.clone()})] => {
+            match shell_surface.state() {
+                Some(&mut Popup(ref popup)) => Some((popup.parent(), popup.geometry())),
+                _ => None
+            }
+        }).unwrap();
         let surface = shell_surface.into();
         with_handles!([(compositor: {compositor})] => {
             let server: &mut Server = compositor.into();
-            let Server { ref mut views, .. } = *server;
+            let Server { ref mut views,
+                         ref mut damage,
+                         .. } = *server;
 
             if let Some(view) = views.iter().find(|view| view.shell == surface).cloned() {
                 if let Some(move_resize) = view.pending_move_resize.get() {
@@ -97,6 +105,14 @@ impl XdgV6ShellHandler for XdgV6 {
                         }
                     }
                 }
+                view.damage(damage);
+            } else if let Some((parent, geometry)) = popup {
+                // Popups aren't views, they are placed relative to their
+                // parent in one.
+                match compositor::popup_box(views, &parent, geometry) {
+                    Some(popup_box) => damage.add_area(popup_box),
+                    None => damage.add_whole()
+                }
             }
         }).unwrap();
     }
@@ -111,40 +127,62 @@ impl XdgV6ShellHandler for XdgV6 {
                 _ => false
             }
         }).unwrap();
-        dehandle!(
+        let mapped = dehandle!(
             @compositor = {compositor};
             let server: &mut Server = compositor.into();
             let Server { ref mut seat,
                          ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
                          ref cursor,
                          ref mut xcursor_manager,
                          .. } = *server;
-            if is_toplevel {
+            let mapped = if is_toplevel {
                 let view = Rc::new(View::new(Shell::XdgV6(shell_surface_handle.into())));
                 views.push(view.clone());
-                seat.focus_view(view, views);
+                view.damage(damage);
+                seat.focus_view(view.clone(), views);
+                Some(view)
+            } else {
+                None
             };
             @cursor = {cursor};
-            seat.update_cursor_position(cursor, xcursor_manager, views, None)
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None);
+            mapped
         );
+        if let Some(view) = mapped {
+            compositor::view_mapped(view);
+        }
     }
 
     fn unmap_request(&mut self,
                      compositor: CompositorHandle,
                      _: SurfaceHandle,
                      shell_surface: XdgV6ShellSurfaceHandle) {
-        dehandle!(
+        let unmapped = dehandle!(
             @compositor = {compositor};
             let server: &mut Server = compositor.into();
             let Server { ref mut seat,
                          ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
                          ref cursor,
                          ref mut xcursor_manager,
                          .. } = *server;
             let destroyed_shell = shell_surface.into();
-            if let Some(pos) = views.iter().position(|view| view.shell == destroyed_shell) {
-                views.remove(pos);
-            };
+            let unmapped = views.iter()
+                                .position(|view| view.shell == destroyed_shell)
+                                .map(|pos| views.remove(pos));
+            if let Some(ref view) = unmapped {
+                view.damage(damage);
+            }
 
             if views.len() > 0 {
                 seat.focus_view(views[0].clone(), views);
@@ -152,8 +190,17 @@ impl XdgV6ShellHandler for XdgV6 {
                 seat.clear_focus();
             };
             @cursor = {cursor};
-            seat.update_cursor_position(cursor, xcursor_manager, views, None)
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None);
+            unmapped
         );
+        if let Some(view) = unmapped {
+            compositor::view_unmapped(&view);
+        }
     }
 }
 
@@ -164,6 +211,6 @@ impl XdgV6ShellManagerHandler for XdgV6ShellManager {
                    _: CompositorHandle,
                    _: XdgV6ShellSurfaceHandle)
                    -> (Option<Box<XdgV6ShellHandler>>, Option<Box<SurfaceHandler>>) {
-        (Some(Box::new(XdgV6::new())), None)
+        (Some(Box::new(XdgV6::new())), Some(Box::new(ViewSurface)))
     }
 }
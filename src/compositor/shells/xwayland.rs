@@ -0,0 +1,218 @@
+use compositor::{self, Action, Server, Shell, View};
+use wlroots::{Area, CompositorHandle, Origin, Size, SurfaceHandle, XWaylandSurfaceHandle,
+              XWaylandSurfaceHandler};
+
+use std::rc::Rc;
+use wlroots::xwayland_surface_events::{ConfigureEvent, MoveEvent, ResizeEvent};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct XWayland {
+    shell_surface: XWaylandSurfaceHandle
+}
+
+impl XWayland {
+    pub fn new() -> Self {
+        XWayland { ..XWayland::default() }
+    }
+}
+
+impl XWaylandSurfaceHandler for XWayland {
+    fn on_configure(&mut self,
+                    compositor: CompositorHandle,
+                    _: Option<SurfaceHandle>,
+                    shell_surface: XWaylandSurfaceHandle,
+                    event: &ConfigureEvent) {
+        // X11 clients expect to be placed where they ask until Lua says otherwise.
+        let area = Area::new(Origin::new(event.x() as i32, event.y() as i32),
+                             Size::new(event.width() as i32, event.height() as i32));
+        with_handles!([(shell_surface: {shell_surface.clone()})] => {
+            shell_surface.configure(event.x(), event.y(), event.width(), event.height());
+        }).unwrap();
+
+        let surface = shell_surface.into();
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut views,
+                         ref mut unmanaged,
+                         ref mut damage,
+                         .. } = *server;
+
+            if let Some(view) = views.iter()
+                                     .chain(unmanaged.iter())
+                                     .find(|view| view.shell == surface) {
+                view.origin.set(area.origin);
+                view.damage(damage);
+            }
+        }).unwrap();
+    }
+
+    fn on_commit(&mut self,
+                 compositor: CompositorHandle,
+                 _: SurfaceHandle,
+                 shell_surface: XWaylandSurfaceHandle) {
+        // Override redirect windows move themselves without asking us first.
+        let (x, y) = with_handles!([(shell_surface: {shell_surface.clone()})] => {
+            shell_surface.coords()
+        }).unwrap();
+
+        let surface = shell_surface.into();
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut views,
+                         ref mut unmanaged,
+                         ref mut damage,
+                         .. } = *server;
+
+            if let Some(view) = unmanaged.iter().find(|view| view.shell == surface) {
+                view.origin.set(Origin::new(x as i32, y as i32));
+            }
+            if let Some(view) = views.iter()
+                                     .chain(unmanaged.iter())
+                                     .find(|view| view.shell == surface) {
+                view.damage(damage);
+            }
+        }).unwrap();
+    }
+
+    fn on_resize(&mut self,
+                 compositor: CompositorHandle,
+                 _: Option<SurfaceHandle>,
+                 shell_surface: XWaylandSurfaceHandle,
+                 event: &ResizeEvent) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut cursor,
+                         .. } = *server;
+            let resizing_shell = shell_surface.into();
+
+            if let Some(view) = views.iter().find(|view| view.shell == resizing_shell).cloned() {
+                seat.begin_resize(cursor, view.clone(), views, event.edges())
+            }
+        }).unwrap();
+    }
+
+    fn on_move(&mut self,
+               compositor: CompositorHandle,
+               _: Option<SurfaceHandle>,
+               shell_surface: XWaylandSurfaceHandle,
+               _: &MoveEvent) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let ref mut seat = server.seat;
+            let ref mut cursor = server.cursor;
+
+            if let Some(ref mut view) = seat.focused {
+                let shell: Shell = shell_surface.into();
+                let action = &mut seat.action;
+                if view.shell == shell {
+                    with_handles!([(cursor: {cursor})] => {
+                        let (lx, ly) = cursor.coords();
+                        let Origin { x: shell_x, y: shell_y } = view.origin.get();
+                        let (view_sx, view_sy) = (lx - shell_x as f64, ly - shell_y as f64);
+                        let start = Origin::new(view_sx as _, view_sy as _);
+                        *action = Some(Action::Moving { start: start });
+                    }).unwrap();
+                }
+            }
+        }).unwrap();
+    }
+
+    fn on_map(&mut self,
+              compositor: CompositorHandle,
+              _: SurfaceHandle,
+              shell_surface_handle: XWaylandSurfaceHandle) {
+        let ((x, y), override_redirect) =
+            with_handles!([(shell_surface: {&shell_surface_handle})] => {
+                (shell_surface.coords(), shell_surface.override_redirect())
+            }).unwrap();
+        let mapped = dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            let view = Rc::new(View::new(Shell::XWayland(shell_surface_handle.into())));
+            view.origin.set(Origin::new(x as i32, y as i32));
+            view.damage(damage);
+            let mapped = if override_redirect {
+                // Menus, tooltips and the like are placed exactly where they
+                // asked to be, and they never take the focus. The newest
+                // is on top, like the views.
+                unmanaged.insert(0, view);
+                None
+            } else {
+                views.push(view.clone());
+                seat.focus_view(view.clone(), views);
+                Some(view)
+            };
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None);
+            mapped
+        );
+        if let Some(view) = mapped {
+            compositor::view_mapped(view);
+        }
+    }
+
+    fn on_unmap(&mut self,
+                compositor: CompositorHandle,
+                _: SurfaceHandle,
+                shell_surface: XWaylandSurfaceHandle) {
+        let unmapped = dehandle!(
+            @compositor = {compositor};
+            let server: &mut Server = compositor.into();
+            let Server { ref mut seat,
+                         ref mut views,
+                         ref mut unmanaged,
+                         ref layer_surfaces,
+                         ref mut damage,
+                         ref cursor,
+                         ref mut xcursor_manager,
+                         .. } = *server;
+            let destroyed_shell = shell_surface.into();
+            let unmapped = if let Some(pos) =
+                unmanaged.iter().position(|view| view.shell == destroyed_shell)
+            {
+                unmanaged.remove(pos).damage(damage);
+                None
+            } else {
+                let unmapped = views.iter()
+                                    .position(|view| view.shell == destroyed_shell)
+                                    .map(|pos| views.remove(pos));
+                if let Some(ref view) = unmapped {
+                    view.damage(damage);
+                }
+
+                if views.len() > 0 {
+                    seat.focus_view(views[0].clone(), views);
+                } else {
+                    seat.clear_focus();
+                };
+                unmapped
+            };
+            @cursor = {cursor};
+            seat.update_cursor_position(cursor,
+                                        xcursor_manager,
+                                        layer_surfaces,
+                                        unmanaged,
+                                        views,
+                                        None);
+            unmapped
+        );
+        if let Some(view) = unmapped {
+            compositor::view_unmapped(&view);
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use compositor::Shell;
+use awesome::{self, LUA};
+use compositor::{self, Damage, Server, Shell};
 use std::cell::Cell;
-use wlroots::XdgV6ShellState::*;
-use wlroots::{Area, Origin, Size, SurfaceHandle};
+use std::rc::Rc;
+use wlroots::{self, Area, CompositorHandle, Origin, Size, SubsurfaceHandle, SurfaceHandle,
+              SurfaceHandler, XdgShellState, XdgV6ShellState};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct PendingMoveResize {
@@ -15,50 +17,134 @@ pub struct PendingMoveResize {
 pub struct View {
     pub shell: Shell,
     pub origin: Cell<Origin>,
-    pub pending_move_resize: Cell<Option<PendingMoveResize>>
+    pub pending_move_resize: Cell<Option<PendingMoveResize>>,
+    /// Where the view was when it was last damaged, in output layout
+    /// coordinates.
+    damaged_box: Cell<Area>
 }
 
 impl View {
     pub fn new(shell: Shell) -> View {
         View { shell: shell,
                origin: Cell::new(Origin::default()),
-               pending_move_resize: Cell::new(None) }
+               pending_move_resize: Cell::new(None),
+               damaged_box: Cell::new(Area::default()) }
+    }
+
+    /// The area covered by the view, including its popups and subsurfaces.
+    pub fn bounding_box(&self) -> Area {
+        let Origin { x, y } = self.origin.get();
+        let mut bounding_box = Area::default();
+        self.for_each_surface(&mut |surface: SurfaceHandle, sx, sy| {
+            let (width, height) = with_handles!([(surface: {surface})] => {
+                surface.current_state().size()
+            }).unwrap();
+            let area = Area::new(Origin::new(x + sx, y + sy), Size::new(width, height));
+            bounding_box = compositor::union(bounding_box, area);
+        });
+        bounding_box
+    }
+
+    /// Where one of the surfaces of the view is, in output layout
+    /// coordinates, or `None` if the surface isn't part of the view.
+    pub fn surface_box(&self, surface: &SurfaceHandle) -> Option<Area> {
+        let Origin { x, y } = self.origin.get();
+        let mut surface_box = None;
+        self.for_each_surface(&mut |other: SurfaceHandle, sx, sy| {
+            if other != *surface {
+                return
+            }
+            let (width, height) = with_handles!([(other: {other})] => {
+                other.current_state().size()
+            }).unwrap();
+            surface_box = Some(Area::new(Origin::new(x + sx, y + sy), Size::new(width, height)));
+        });
+        surface_box
+    }
+
+    /// Damages where the view was the last time it was damaged, and where it
+    /// is now.
+    pub fn damage(&self, damage: &mut Damage) {
+        let bounding_box = self.bounding_box();
+        damage.add_area(self.damaged_box.replace(bounding_box));
+        damage.add_area(bounding_box);
     }
 
     pub fn surface(&self) -> SurfaceHandle {
         match self.shell {
+            Shell::Xdg(ref xdg_surface) => {
+                with_handles!([(xdg_surface: {xdg_surface})] => {
+                    xdg_surface.surface()
+                }).unwrap()
+            }
             Shell::XdgV6(ref xdg_surface) => {
                 with_handles!([(xdg_surface: {xdg_surface})] => {
                     xdg_surface.surface()
                 }).unwrap()
             }
+            Shell::XWayland(ref xwayland_surface) => {
+                let surface = with_handles!([(xwayland_surface: {xwayland_surface})] => {
+                    xwayland_surface.surface()
+                }).unwrap();
+                surface.expect("An XWayland client did not provide us a surface")
+            }
         }
     }
 
     pub fn activate(&self, activate: bool) {
         match self.shell {
+            Shell::Xdg(ref xdg_surface) => {
+                dehandle! (
+                    @xdg_surface = {xdg_surface};
+                    match xdg_surface.state() {
+                        Some(&mut XdgShellState::TopLevel(ref mut toplevel)) => {
+                            toplevel.set_activated(activate);
+                        },
+                        // Only toplevels can be activated, popups go with them.
+                        _ => {}
+                    }
+                );
+            }
             Shell::XdgV6(ref xdg_surface) => {
                 dehandle! (
                     @xdg_surface = {xdg_surface};
                     match xdg_surface.state() {
-                        Some(&mut TopLevel(ref mut toplevel)) => {
+                        Some(&mut XdgV6ShellState::TopLevel(ref mut toplevel)) => {
                             toplevel.set_activated(activate);
                         },
-                        _ => unimplemented!()
+                        // Only toplevels can be activated, popups go with them.
+                        _ => {}
                     }
                 );
             }
+            Shell::XWayland(ref xwayland_surface) => {
+                with_handles!([(xwayland_surface: {xwayland_surface})] => {
+                    xwayland_surface.activate(activate);
+                }).unwrap();
+            }
         }
     }
 
     pub fn get_size(&self) -> Size {
         match self.shell {
+            Shell::Xdg(ref xdg_surface) => {
+                with_handles!([(xdg_surface: {xdg_surface})] => {
+                    let Area { origin: _, size } = xdg_surface.geometry();
+                    size
+                }).unwrap()
+            }
             Shell::XdgV6(ref xdg_surface) => {
                 with_handles!([(xdg_surface: {xdg_surface})] => {
                     let Area { origin: _, size } = xdg_surface.geometry();
                     size
                 }).unwrap()
             }
+            Shell::XWayland(ref xwayland_surface) => {
+                with_handles!([(xwayland_surface: {xwayland_surface})] => {
+                    let (width, height) = xwayland_surface.dimensions();
+                    Size::new(width as i32, height as i32)
+                }).unwrap()
+            }
         }
     }
 
@@ -76,17 +162,37 @@ impl View {
         let mut serial = 0;
 
         match self.shell {
+            Shell::Xdg(ref xdg_surface) => {
+                with_handles!([(xdg_surface: {xdg_surface})] => {
+                    match xdg_surface.state() {
+                        Some(&mut XdgShellState::TopLevel(ref mut toplevel)) => {
+                            // TODO apply size constraints
+                            serial = toplevel.set_size(width, height);
+                        },
+                        // Popups are placed relative to their parent.
+                        _ => {}
+                    }
+                }).unwrap();
+            }
             Shell::XdgV6(ref xdg_surface) => {
                 with_handles!([(xdg_surface: {xdg_surface})] => {
                     match xdg_surface.state() {
-                        Some(&mut TopLevel(ref mut toplevel)) => {
+                        Some(&mut XdgV6ShellState::TopLevel(ref mut toplevel)) => {
                             // TODO apply size constraints
                             serial = toplevel.set_size(width, height);
                         },
-                        _ => unimplemented!()
+                        // Popups are placed relative to their parent.
+                        _ => {}
                     }
                 }).unwrap();
             }
+            Shell::XWayland(ref xwayland_surface) => {
+                // X11 has no configure serials, the client gets the new
+                // geometry right away.
+                with_handles!([(xwayland_surface: {xwayland_surface})] => {
+                    xwayland_surface.configure(x as i16, y as i16, width as u16, height as u16);
+                }).unwrap();
+            }
         }
 
         if serial == 0 {
@@ -102,11 +208,95 @@ impl View {
 
     pub fn for_each_surface(&self, f: &mut FnMut(SurfaceHandle, i32, i32)) {
         match self.shell {
+            Shell::Xdg(ref xdg_surface) => {
+                with_handles!([(xdg_surface: {xdg_surface})] => {
+                    xdg_surface.for_each_surface(f);
+                }).unwrap();
+            }
             Shell::XdgV6(ref xdg_surface) => {
                 with_handles!([(xdg_surface: {xdg_surface})] => {
                     xdg_surface.for_each_surface(f);
                 }).unwrap();
             }
+            Shell::XWayland(ref xwayland_surface) => {
+                let surface = with_handles!([(xwayland_surface: {xwayland_surface})] => {
+                    xwayland_surface.surface()
+                }).unwrap();
+                if let Some(surface) = surface {
+                    with_handles!([(surface: {surface})] => {
+                        surface.for_each_surface(f);
+                    }).unwrap();
+                }
+            }
         }
     }
 }
+
+/// Damages a surface of a view when it commits, e.g a subsurface that draws
+/// on its own or a popup.
+///
+/// Subsurfaces get one as well, so that every surface of the view has one.
+pub struct ViewSurface;
+
+impl SurfaceHandler for ViewSurface {
+    fn on_commit(&mut self, compositor: CompositorHandle, surface: SurfaceHandle) {
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            let Server { ref views,
+                         ref unmanaged,
+                         ref mut damage,
+                         .. } = *server;
+            let surface_box = views.iter()
+                                   .chain(unmanaged.iter())
+                                   .filter_map(|view| view.surface_box(&surface))
+                                   .next();
+            if let Some(surface_box) = surface_box {
+                damage.add_area(surface_box);
+            }
+        }).unwrap();
+    }
+
+    fn new_subsurface(&mut self,
+                      _: CompositorHandle,
+                      _: SurfaceHandle,
+                      _: SubsurfaceHandle)
+                      -> Option<Box<SurfaceHandler>> {
+        Some(Box::new(ViewSurface))
+    }
+}
+
+/// The views that are shown, topmost first, from outside of a compositor
+/// callback (e.g from Lua).
+///
+/// Override redirect XWayland windows aren't included.
+pub fn views() -> Vec<Rc<View>> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.views.clone()
+    )
+}
+
+/// Lets Lua know that a view was mapped, so that it gets a client.
+///
+/// Must be called outside of a compositor callback.
+pub fn view_mapped(view: Rc<View>) {
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 if let Err(err) = awesome::manage_client(&*lua, view) {
+                     warn!("Could not make a client for a view: {:?}", err);
+                 }
+             });
+}
+
+/// Lets Lua know that a view was unmapped, so that its client goes away.
+///
+/// Must be called outside of a compositor callback.
+pub fn view_unmapped(view: &Rc<View>) {
+    LUA.with(|lua| {
+                 let lua = lua.borrow();
+                 if let Err(err) = awesome::unmanage_client(&*lua, view) {
+                     warn!("Could not remove the client of a view: {:?}", err);
+                 }
+             });
+}
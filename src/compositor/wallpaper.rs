@@ -0,0 +1,220 @@
+//! The wallpaper drawn beneath everything else, set from Lua with
+//! `root.wallpaper`.
+//!
+//! Lua gives us a cairo pattern covering the whole output layout, which is
+//! rasterised separately for each output at its scale.
+
+use std::fmt::{self, Debug, Formatter};
+
+use cairo::{Context, Format, ImageSurface};
+use cairo_sys::{self, cairo_pattern_t};
+use glib::translate::ToGlibPtr;
+use wlroots::{self, Area, OutputHandle, Renderer, Size, Texture, WL_SHM_FORMAT_ARGB8888};
+
+use compositor::{self, Server};
+
+/// The colour drawn when there is no wallpaper.
+pub const DEFAULT_BACKGROUND: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+
+/// A reference to a cairo pattern.
+struct Pattern(*mut cairo_pattern_t);
+
+impl Pattern {
+    /// Takes a new reference to the pattern.
+    unsafe fn new(pattern: *mut cairo_pattern_t) -> Self {
+        Pattern(cairo_sys::cairo_pattern_reference(pattern))
+    }
+
+    /// Paints the pattern over the whole surface.
+    fn paint(&self, context: &Context) {
+        unsafe {
+            cairo_sys::cairo_set_source(context.to_raw_none(), self.0);
+        }
+        context.paint();
+    }
+}
+
+impl Drop for Pattern {
+    fn drop(&mut self) {
+        unsafe { cairo_sys::cairo_pattern_destroy(self.0) }
+    }
+}
+
+pub struct Wallpaper {
+    pattern: Option<Pattern>,
+    /// The wallpaper of the whole layout, at a scale of 1.
+    surface: Option<ImageSurface>,
+    /// The wallpaper rasterised for each output it was drawn on.
+    textures: Vec<(OutputHandle, Texture<'static>)>,
+    /// What is drawn where there is no wallpaper.
+    pub background: [f32; 4]
+}
+
+impl Default for Wallpaper {
+    fn default() -> Self {
+        Wallpaper { pattern: None,
+                    surface: None,
+                    textures: Vec::new(),
+                    background: DEFAULT_BACKGROUND }
+    }
+}
+
+impl Debug for Wallpaper {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Wallpaper")
+         .field("set", &self.pattern.is_some())
+         .field("textures", &self.textures.len())
+         .field("background", &self.background)
+         .finish()
+    }
+}
+
+impl Wallpaper {
+    /// Sets the wallpaper to the pattern, which covers the whole layout.
+    ///
+    /// `root_box` is the area covered by the layout.
+    pub unsafe fn set(&mut self,
+                      pattern: *mut cairo_pattern_t,
+                      root_box: Area)
+                      -> Result<(), String> {
+        let pattern = Pattern::new(pattern);
+        let Size { width, height } = root_box.size;
+        let surface = ImageSurface::create(Format::ARgb32, width.max(1), height.max(1))
+            .map_err(|err| format!("Could not allocate wallpaper: {:?}", err))?;
+        {
+            let context = Context::new(&surface);
+            context.translate(-root_box.origin.x as f64, -root_box.origin.y as f64);
+            pattern.paint(&context);
+        }
+        self.surface = Some(surface);
+        self.pattern = Some(pattern);
+        self.invalidate();
+        Ok(())
+    }
+
+    /// The wallpaper of the whole layout, if there is one.
+    pub fn surface(&self) -> Option<&ImageSurface> {
+        self.surface.as_ref()
+    }
+
+    /// Forgets the textures of the outputs, e.g because they moved around.
+    pub fn invalidate(&mut self) {
+        for (_, texture) in self.textures.drain(..) {
+            compositor::free_texture(texture);
+        }
+    }
+
+    /// Renders the wallpaper onto the output being rendered, or clears it
+    /// with the background colour if there is none.
+    ///
+    /// `output_box` is where the output is in the layout.
+    pub fn render(&mut self, renderer: &mut Renderer, output_box: Area) {
+        renderer.clear(self.background);
+        let output = renderer.output.weak_reference();
+        let scale = renderer.output.scale();
+        if !self.textures.iter().any(|&(ref other, _)| *other == output) {
+            let texture = match self.rasterise(renderer, output_box, scale) {
+                Ok(texture) => texture,
+                Err(err) => {
+                    warn!("{}", err);
+                    return
+                }
+            };
+            match texture {
+                Some(texture) => self.textures.push((output.clone(), texture)),
+                None => return
+            }
+        }
+        let texture = match self.textures.iter().find(|&&(ref other, _)| *other == output) {
+            Some(&(_, ref texture)) => texture,
+            None => return
+        };
+        let render_box = compositor::output_coords(output_box, output_box, scale);
+        let transform = renderer.output.get_transform().invert();
+        let matrix =
+            wlroots::project_box(render_box, transform, 0.0, renderer.output.transform_matrix());
+        renderer.render_texture_with_matrix(texture, matrix);
+    }
+
+    /// Draws the pattern for the output at its scale, and uploads it.
+    fn rasterise(&self,
+                 renderer: &mut Renderer,
+                 output_box: Area,
+                 scale: f32)
+                 -> Result<Option<Texture<'static>>, String> {
+        let pattern = match self.pattern {
+            Some(ref pattern) => pattern,
+            None => return Ok(None)
+        };
+        let Size { width, height } = output_box.size;
+        let width = (width as f32 * scale).ceil() as i32;
+        let height = (height as f32 * scale).ceil() as i32;
+        if width <= 0 || height <= 0 {
+            return Ok(None)
+        }
+        let mut surface = ImageSurface::create(Format::ARgb32, width, height)
+            .map_err(|err| format!("Could not allocate wallpaper: {:?}", err))?;
+        surface.set_device_scale(scale as f64, scale as f64);
+        {
+            let context = Context::new(&surface);
+            context.translate(-output_box.origin.x as f64, -output_box.origin.y as f64);
+            pattern.paint(&context);
+        }
+        surface.flush();
+        let stride = surface.get_stride();
+        let data = compositor::get_data(&mut surface);
+        let texture = renderer.create_texture_from_pixels(WL_SHM_FORMAT_ARGB8888,
+                                                          stride as _,
+                                                          width as _,
+                                                          height as _,
+                                                          data)
+                              .ok_or_else(|| "Could not upload the wallpaper".to_string())?;
+        Ok(Some(texture))
+    }
+}
+
+/// Sets the wallpaper from outside of a compositor callback, e.g from Lua.
+///
+/// The pattern covers the whole output layout.
+pub unsafe fn set_wallpaper(pattern: *mut cairo_pattern_t) -> Result<(), String> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        let Server { ref mut layout,
+                     ref mut wallpaper,
+                     ref mut damage,
+                     .. } = *server;
+        let root_box = with_handles!([(layout: {&mut *layout})] => {
+            layout.get_box(None)
+        }).unwrap();
+        let res = wallpaper.set(pattern, root_box);
+        damage.add_whole();
+        res
+    )
+}
+
+/// Gets the wallpaper of the whole layout, from outside of a compositor
+/// callback (e.g from Lua).
+///
+/// Returns a new reference to the cairo surface.
+pub fn wallpaper_surface() -> Option<*mut cairo_sys::cairo_surface_t> {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.wallpaper.surface().map(|surface| {
+            let ptr = surface.to_glib_none().0;
+            unsafe { cairo_sys::cairo_surface_reference(ptr) }
+        })
+    )
+}
+
+/// Sets the colour drawn where there is no wallpaper, from outside of a
+/// compositor callback (e.g from Lua).
+pub fn set_background(color: [f32; 4]) {
+    dehandle!(
+        @compositor = {wlroots::compositor_handle().unwrap()};
+        let server: &mut Server = compositor.into();
+        server.wallpaper.background = color;
+        server.damage.add_whole()
+    )
+}
@@ -1,11 +1,42 @@
 //! This handles the XWayland server and any XWayland clients that connect to
 //! Way Cooler.
 
-use std::panic;
+use rlua::Value;
 use wlroots::{CompositorHandle, SurfaceHandler, XWaylandManagerHandler, XWaylandSurfaceHandle,
               XWaylandSurfaceHandler};
 
-use awesome;
+use awesome::{self, LUA};
+use compositor::{Server, ViewSurface, XWayland};
+
+/// How (and if) the XWayland server should be started.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum XWaylandMode {
+    /// Never start XWayland, X11 clients will not be able to connect.
+    Disabled,
+    /// Start XWayland as soon as the compositor is running.
+    Eager,
+    /// Start XWayland the first time an X11 client connects.
+    Lazy
+}
+
+impl Default for XWaylandMode {
+    fn default() -> Self {
+        XWaylandMode::Eager
+    }
+}
+
+impl XWaylandMode {
+    /// Parses the mode from its name, e.g "lazy" or "off".
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "on" | "eager" => Ok(XWaylandMode::Eager),
+            "lazy" => Ok(XWaylandMode::Lazy),
+            "off" | "disabled" => Ok(XWaylandMode::Disabled),
+            name => Err(format!("Unknown XWayland mode \"{}\", expected one of on, lazy or off",
+                                name))
+        }
+    }
+}
 
 pub struct XWaylandManager;
 
@@ -17,20 +48,29 @@ impl XWaylandManager {
 
 impl XWaylandManagerHandler for XWaylandManager {
     fn on_ready(&mut self, compositor: CompositorHandle) {
-        // TODO Do this properly with Results!
-        match panic::catch_unwind(panic::AssertUnwindSafe(|| ::awesome::lua::setup_lua(compositor)))
-        {
-            Ok(_) => {}
-            Err(err) => {
-                awesome::lua::terminate();
-                panic::resume_unwind(err)
-            }
-        }
+        info!("XWayland is ready");
+        with_handles!([(compositor: {compositor})] => {
+            let server: &mut Server = compositor.into();
+            server.xwayland_ready = true;
+        }).unwrap();
+        // Lua was started without an X connection, so give it one now.
+        LUA.with(|lua| {
+                     let lua = lua.borrow();
+                     if let Err(err) = awesome::setup_xcb_connection(&*lua) {
+                         warn!("Could not connect to XWayland: {:?}", err);
+                         return
+                     }
+                     let signal = ("xkb::map_changed".into(), Value::Nil);
+                     if let Err(err) = awesome::global_emit_signal(&*lua, signal) {
+                         warn!("Could not emit xkb::map_changed: {:?}", err);
+                     }
+                 });
     }
+
     fn new_surface(&mut self,
                    _: CompositorHandle,
                    _: XWaylandSurfaceHandle)
                    -> (Option<Box<XWaylandSurfaceHandler>>, Option<Box<SurfaceHandler>>) {
-        (None, None)
+        (Some(Box::new(XWayland::new())), Some(Box::new(ViewSurface)))
     }
 }
@@ -28,7 +28,8 @@ mod compositor;
 
 pub use awesome::{refresh_awesome, lua};
 
-use std::{env, fs::File, io::{BufRead, BufReader}, os::raw::c_void, path::Path, process::exit};
+use std::{env, fs::File, io::{BufRead, BufReader}, os::raw::c_void, panic, path::Path,
+          process::exit};
 
 use log::LogLevel;
 use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet};
@@ -41,6 +42,22 @@ const DEVICE_MOD_PATH: &'static str = "/sys/firmware/devicetree/base/model";
 fn main() {
     let mut opts = getopts::Options::new();
     opts.optflag("", "version", "show version information");
+    opts.optopt("",
+                "backend",
+                "backend to run on: auto, drm, wayland, x11 or headless",
+                "BACKEND");
+    opts.optopt("",
+                "outputs",
+                "number of outputs to create for the wayland, x11 and headless backends",
+                "COUNT");
+    opts.optopt("",
+                "output-size",
+                "size of each output of the headless backend",
+                "WIDTHxHEIGHT");
+    opts.optopt("",
+                "xwayland",
+                "when to start XWayland: on, lazy (on the first X11 client) or off",
+                "MODE");
     let matches = match opts.parse(env::args().skip(1)) {
         Ok(m) => m,
         Err(f) => {
@@ -56,6 +73,20 @@ fn main() {
         }
         return
     }
+    let backend = match parse_backend(&matches) {
+        Ok(backend) => backend,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    let xwayland_mode = match parse_xwayland_mode(&matches) {
+        Ok(mode) => mode,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
     println!("Launching way-cooler...");
 
     let sig_action = SigAction::new(SigHandler::Handler(sig_handle),
@@ -70,8 +101,7 @@ fn main() {
     detect_proprietary();
     detect_raspi();
     ensure_good_env();
-    let compositor = compositor::init();
-    assert!(compositor.xwayland.is_some());
+    let compositor = compositor::init(backend, xwayland_mode);
     unsafe {
         #[link(name = "wayland_glib_interface", kind = "static")]
         extern "C" {
@@ -79,7 +109,44 @@ fn main() {
         }
         wayland_glib_interface_init(compositor.display as *mut c_void);
     }
-    compositor.run_with(|_| awesome::lua::enter_glib_loop());
+    compositor.run_with(|compositor| {
+                            // TODO Do this properly with Results!
+                            let setup = || awesome::lua::setup_lua(compositor.weak_reference());
+                            if let Err(err) = panic::catch_unwind(panic::AssertUnwindSafe(setup)) {
+                                awesome::lua::terminate();
+                                panic::resume_unwind(err)
+                            }
+                            awesome::lua::enter_glib_loop()
+                        });
+}
+
+/// Determines the backend to run on from the command line, falling back on
+/// the `WAY_COOLER_BACKEND`, `WAY_COOLER_OUTPUTS` and
+/// `WAY_COOLER_OUTPUT_SIZE` environment variables.
+fn parse_backend(matches: &getopts::Matches) -> Result<compositor::Backend, String> {
+    let option = |name: &str, var: &str| matches.opt_str(name).or_else(|| env::var(var).ok());
+    let outputs = match option("outputs", "WAY_COOLER_OUTPUTS") {
+        Some(outputs) => {
+            Some(outputs.parse::<usize>()
+                        .map_err(|_| format!("Invalid output count \"{}\"", outputs))?)
+        }
+        None => None
+    };
+    let size = match option("output-size", "WAY_COOLER_OUTPUT_SIZE") {
+        Some(size) => Some(compositor::parse_size(&size)?),
+        None => None
+    };
+    let name = option("backend", "WAY_COOLER_BACKEND").unwrap_or_else(|| "auto".into());
+    compositor::Backend::from_name(name.as_str(), outputs, size)
+}
+
+/// Determines when to start XWayland from the command line, falling back on
+/// the `WAY_COOLER_XWAYLAND` environment variable.
+fn parse_xwayland_mode(matches: &getopts::Matches) -> Result<compositor::XWaylandMode, String> {
+    match matches.opt_str("xwayland").or_else(|| env::var("WAY_COOLER_XWAYLAND").ok()) {
+        Some(mode) => compositor::XWaylandMode::from_name(mode.as_str()),
+        None => Ok(compositor::XWaylandMode::default())
+    }
 }
 
 /// Formats the log strings properly